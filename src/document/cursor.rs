@@ -1,9 +1,9 @@
 use tblit::Vec2;
-use crate::document::Document;
+use crate::document::{Document, LineColumn, Unit};
 
 /// Document user cursor.
 pub struct Cursor {
-    /// The xy position of the cursor in the document. 
+    /// The xy position of the cursor in the document, with x counted in chars.
     pub position: Vec2<usize>,
 
     /// The current byte position of the cursor in the document.
@@ -29,9 +29,8 @@ impl Cursor {
     pub fn write(&mut self, document: &mut Document, input: &str) {
         document.edit((self.offset, self.offset), input);
 
-        for _ in 0..input.len() {
-            self.next_char(document);
-        }
+        self.move_to(document, self.offset + input.len());
+        self.sticky_x = self.position.x;
     }
 
     /// Remove the character beffor the cursor position.
@@ -40,51 +39,28 @@ impl Cursor {
             return
         }
 
+        let end = self.offset;
         self.prev_char(document);
 
-        document.edit((self.offset, self.offset + 1), "");
+        document.edit((self.offset, end), "");
     }
 }
 
 impl Cursor {
-    /// Moves to next character, without bound checking.
-    fn next(&mut self, document: &Document) {
-        if document.text.is_newline(self.offset) {
-            self.position.y += 1;
-            self.position.x = 0;
-        } else {
-            self.position.x += 1;
-        }
-
-        self.offset += 1;
-    }
+    /// Moves to a byte offset, and works out the xy position from the line index.
+    fn move_to(&mut self, document: &Document, offset: usize) {
+        let position = document.text.line_column(offset, Unit::Char);
 
-    /// Moves to previous character, without bound checking.
-    fn prev(&mut self, document: &Document) {
-        self.offset -= 1;
-
-        if document.text.is_newline(self.offset) {
-            self.position.y -= 1;
-            self.position.x = 0;
-
-            while
-                self.position.x != self.offset &&
-                !document.text.is_newline(self.offset - self.position.x - 1)
-            {
-                self.position.x += 1;
-            }
-        } else {
-            self.position.x -= 1;
-        }
+        self.offset = offset;
+        self.position = Vec2::new(position.column, position.line);
     }
 }
 
 impl Cursor {
     /// Moves the cursor to the next character. Also updates the sticky x position.
     pub fn next_char(&mut self, document: &Document) {
-        // Go to the next line if were not at the end.
-        if self.offset != document.text.byte_len() { 
-            self.next(&document);
+        if let Some(chr) = document.text.read(self.offset) {
+            self.move_to(document, self.offset + chr.len_utf8());
         }
 
         self.sticky_x = self.position.x;
@@ -93,29 +69,22 @@ impl Cursor {
     /// Moves the cursor to the previous character. Also updates the sticky x position.
     pub fn prev_char(&mut self, document: &Document) {
         if self.offset != 0 {
-            self.prev(&document);
+            let chars = document.text.byte_to_unit(self.offset, Unit::Char);
+            self.move_to(document, document.text.unit_to_byte(chars - 1, Unit::Char));
         }
-        
+
         self.sticky_x = self.position.x;
     }
 
     /// Moves the cursor to the next line at the current sticky x.
     pub fn next_line(&mut self, document: &Document) {
-        // Dont do anything if at the end of the document
-        if self.offset == document.text.byte_len() {
+        // On the last line, move to the end of the document instead
+        if self.position.y + 1 >= document.text.line_count() {
+            self.move_to(document, document.text.byte_len());
             return
         }
 
-        // move to next line
-        self.next(document);
-        while self.position.x != 0 && self.offset < document.text.byte_len() {
-            self.next(document);
-        }
-
-        // move back to the previous x value, or to the end of line
-        while self.position.x < self.sticky_x && !document.text.is_newline(self.offset) {
-            self.next(document);
-        }
+        self.move_to(document, self.sticky_offset(document, self.position.y + 1));
     }
 
     /// Moves the cursor to the previous line at the current sticky x.
@@ -125,16 +94,13 @@ impl Cursor {
             return
         }
 
-        // move down to previous line
-        self.prev(document);
-        while !document.text.is_newline(self.offset) {
-            self.prev(document);
-        }
+        self.move_to(document, self.sticky_offset(document, self.position.y - 1));
+    }
 
-        // move down to correct position in line
-        while self.position.x > self.sticky_x {
-            self.prev(document);
-        }
+    /// The offset at the sticky x on a line, or the end of the line if it is shorter.
+    fn sticky_offset(&self, document: &Document, line: usize) -> usize {
+        let position = LineColumn { line, column: self.sticky_x };
+
+        return document.text.offset(position, Unit::Char);
     }
 }
-
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::document::{Kind, Kinds, Span};
+
+/// How bad a diagnostic is.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Severity {
+    /// The text doesn't follow the language.
+    Error,
+
+    /// The text is fine, but probably not what was meant.
+    Warning,
+}
+
+/// Something the parser was looking for.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Expected {
+    /// A node of the kind.
+    Kind(Kind),
+
+    /// A node with exactly the text, like a keyword or an operator.
+    Text(String),
+}
+
+impl Expected {
+    /// Describe it for a message, using the names in the table.
+    pub fn describe(&self, kinds: &Kinds) -> String {
+        return match self {
+            Expected::Kind(kind) => kinds.name(*kind).to_string(),
+            Expected::Text(text) => format!("`{}`", text),
+        };
+    }
+}
+
+/// A syntax error, kept on the node it is about. The node's span is where the error is, so it
+/// moves along with the node when the text is edited.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SyntaxError {
+    /// What went wrong.
+    pub message: String,
+
+    /// What the parser was looking for where it went wrong.
+    pub expected: Vec<Expected>,
+}
+
+/// A problem in a document, for showing to the user.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    /// The part of the text with the problem.
+    pub span: Span,
+
+    /// How bad it is.
+    pub severity: Severity,
+
+    /// What went wrong.
+    pub message: String,
+
+    /// What the parser was looking for there.
+    pub expected: Vec<Expected>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}: {}", self.span.0, self.span.1, self.message)
+    }
+}
+
+/// List the expected things for a message, like "`=`, `+` or Number".
+pub fn describe(expected: &[Expected], kinds: &Kinds) -> String {
+    let names: Vec<String> = expected.iter().map(|expected| expected.describe(kinds)).collect();
+
+    return match names.split_last() {
+        None => "nothing".to_string(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    };
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+/// The kind of a node. Kinds are interned by name in the language's `Kinds` table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Kind(usize);
+
+impl Kind {
+    /// The kind of the root node of a document.
+    pub const FILE: Kind = Kind(0);
+
+    /// The kind of a node that failed to parse.
+    pub const ERROR: Kind = Kind(1);
+
+    /// The position of the kind in its table.
+    pub fn index(self) -> usize {
+        return self.0;
+    }
+}
+
+/// A table of named kinds.
+#[derive(Clone, Debug)]
+pub struct Kinds {
+    /// The name of each kind, by index.
+    names: Vec<String>,
+
+    /// The kind for each name.
+    ids: HashMap<String, Kind>,
+}
+
+impl Kinds {
+    /// Initializes a table with the builtin `File` and `Error` kinds.
+    pub fn new() -> Kinds {
+        let mut kinds = Kinds {
+            names: vec![],
+            ids: HashMap::new(),
+        };
+
+        kinds.intern("File");
+        kinds.intern("Error");
+
+        return kinds;
+    }
+
+    /// Get the kind with the given name, adding it to the table if its new.
+    pub fn intern(&mut self, name: &str) -> Kind {
+        if let Some(kind) = self.ids.get(name) {
+            return *kind;
+        }
+
+        let kind = Kind(self.names.len());
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), kind);
+
+        return kind;
+    }
+
+    /// Find the kind with the given name.
+    pub fn get(&self, name: &str) -> Option<Kind> {
+        return self.ids.get(name).copied();
+    }
+
+    /// Get the name of a kind.
+    pub fn name(&self, kind: Kind) -> &str {
+        return &self.names[kind.0];
+    }
+
+    /// The number of kinds in the table.
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
+
+    /// If the table has no kinds.
+    pub fn is_empty(&self) -> bool {
+        return self.names.is_empty();
+    }
+
+    /// Iterate over all the kinds in the table.
+    pub fn iter(&self) -> impl Iterator<Item = Kind> {
+        return (0..self.names.len()).map(Kind);
+    }
+}
+
+impl Default for Kinds {
+    fn default() -> Kinds {
+        return Kinds::new();
+    }
+}
@@ -4,57 +4,106 @@
 mod cursor;
 mod parser;
 mod nodeiter;
+mod kind;
+mod state;
+mod diagnostic;
+mod text;
+
+#[cfg(test)]
+mod tests;
 
 // Publish
 pub use cursor::Cursor;
-pub use parser::Parser;
+pub use kind::{Kind, Kinds};
+pub use parser::{Checkpoint, Parser, ParseStats};
+pub use state::State;
+pub use diagnostic::{Diagnostic, Expected, Severity, SyntaxError};
 pub use nodeiter::NodeIter;
+pub use text::{CharIndices, LineColumn, Text, Unit};
 
-use std::{rc::Rc, str::CharIndices};
+use std::rc::Rc;
 use crate::rules::{Language, Rule};
 
 /// A span of the document in bytes.
 pub type Span = (usize, usize);
 
-/// Possible kind for a node.
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum Kind {
-    File,
-    Whitespace,
-    Name,
-    Number,
-    Punctuation,
-    Error,
-    EqualExpression,
-}
-
 /// A node in the document.
+#[derive(Clone)]
 pub struct Node<'a> {
     /// The span of the node in the document.
     pub span: (usize, usize),
 
+    /// The end of the text the parser looked at to make the node. Parsers can look past the
+    /// end of a node to decide where it ends, so an edit up to here could change it.
+    pub lookahead: usize,
+
     /// What rule created the node.
     pub rule: &'a Box<dyn Rule>,
 
+    /// The argument the rule was parsed with, like the lowest precedence a Pratt rule takes.
+    pub arg: u32,
+
     /// The kind of node it is.
     pub kind: Kind,
 
     /// The sub value of the nodes.
     pub subs: Vec<Rc<Node<'a>>>,
+
+    /// The state of the parser at the start and end of the node.
+    pub state: (Rc<State>, Rc<State>),
+
+    /// What is wrong with the node, if it was made by recovering from a syntax error.
+    pub error: Option<Rc<SyntaxError>>,
+
+    /// The number of syntax errors in the node and everything under it.
+    pub error_count: usize,
+
+    /// Was the node made up by error recovery, rather than parsed? Missing nodes and the error
+    /// nodes around skipped text are.
+    pub recovered: bool,
+
+    /// The farthest offset something was expected at but wasn't found while parsing the node,
+    /// and what was expected there. The parser is told again when the node is reused, so what
+    /// the errors after it say doesn't depend on what was reused.
+    pub expected: (usize, Vec<Expected>),
+
+    /// Is the node only a part of its rule's result, like the nodes a rule builds itself, or
+    /// the seeds a left recursive result grew from? Only results can be reused, as only they
+    /// are what the rule gives at their offset.
+    pub nested: bool,
+}
+
+impl<'a> Node<'a> {
+    /// Is this an empty node standing in for one that should have been here?
+    pub fn is_missing(&self) -> bool {
+        return self.recovered && self.span.0 == self.span.1;
+    }
+
+    /// Count the syntax errors in a node with the given error and children.
+    pub fn count_errors(error: &Option<Rc<SyntaxError>>, subs: &[Rc<Node<'a>>]) -> usize {
+        return error.iter().count() + subs.iter().map(|sub| sub.error_count).sum::<usize>();
+    }
 }
 
 /// Updates the span of all the nodes when the document is changed.
 fn incrament_node(node: &mut Rc<Node>, removed: usize, added: usize, start: usize) {
     let node = Rc::get_mut(node).expect("extra copy of node exists!");
 
-    if node.span.0 >= start {
-        if node.span.0 > start {
-            node.span.0 = node.span.0 - removed + added;
-            node.span.1 = node.span.1 - removed + added;
+    // Offsets up to the start of the edit stay put, so an empty node there stays in front of
+    // the new text. Offsets in the removed text end up after the new text.
+    let shift = |offset: usize| {
+        if offset <= start {
+            offset
+        } else if offset >= start + removed {
+            offset - removed + added
         } else {
-            node.span.1 = node.span.1 - removed + added;
+            start + added
         }
-    }
+    };
+
+    node.span = (shift(node.span.0), shift(node.span.1));
+    node.lookahead = shift(node.lookahead);
+    node.expected.0 = shift(node.expected.0);
 
     for child in &mut node.subs {
         incrament_node(child, removed, added, start);
@@ -71,19 +120,31 @@ pub struct Document<'a> {
 
     /// The actual String of the Document
     pub text: Text,
+
+    /// Statistics about the last parse.
+    pub stats: ParseStats,
 }
 
 impl<'a> Document<'a> {
     /// Initializes a new document of the given language.
     pub fn new(language: &'a Language) -> Document<'a> {
         return Document {
-            text: Text("".to_string()),
+            text: Text::new(),
+            stats: ParseStats::default(),
             lang: language,
             root: Rc::new(Node {
                 span: (0, 0),
-                rule: &language[0],
-                kind: Kind::File,
+                lookahead: 0,
+                rule: &language.rules[0],
+                arg: 0,
+                kind: Kind::FILE,
                 subs: vec![],
+                state: Default::default(),
+                error: None,
+                error_count: 0,
+                expected: (0, vec![]),
+                recovered: false,
+                nested: false,
             }),
         };
     }
@@ -97,11 +158,10 @@ impl<'a> Document<'a> {
 
         incrament_node(&mut self.root, removed, edit_len, span.0);
 
-        if self.text.byte_len() == 0 {
-            return;
-        }
+        let (root, stats) = parser::parse(self, (span.0, span.0 + edit_len));
 
-        self.root = parser::parse(self, (span.0, span.0 + edit_len));
+        self.root = root;
+        self.stats = stats;
     }
 }
 
@@ -117,7 +177,8 @@ impl<'a> Document<'a> {
         return NodeIter::new(self);
     }
     
-    ///
+    /// The innermost node covering the character at the offset. Empty nodes don't cover
+    /// anything, so they are never found.
     pub fn get<'b>(&'b self, offset: usize) -> Rc<Node<'a>> {
         let mut node = &self.root;
         
@@ -128,6 +189,11 @@ impl<'a> Document<'a> {
         return node.clone();
     }
 
+    /// The text a node covers.
+    pub fn node_text(&self, node: &Node) -> String {
+        return self.text.slice(node.span);
+    }
+
     ///
     pub fn get_filter<'b, T>(
         &'b self,
@@ -150,35 +216,26 @@ impl<'a> Document<'a> {
 
         return None;
     }
-}
-
-/// The Source for a document.
-pub struct Text(String);
-
-impl Text {
-    /// Replace the given span of text with the edit.
-    pub fn edit(&mut self, span: Span, edit: &str) {
-        self.0.replace_range(span.0..span.1, edit);
-    }
-
-    /// Read the character at a given byte offset.
-    pub fn read(&self, offset: usize) -> Option<char> {
-        self.0[offset..].chars().nth(0)
-    }
 
-    /// Cheacks if the character at the given byte offset is a newline.
-    pub fn is_newline(&self, offset: usize) -> bool {
-        self.read(offset).map_or(true, |chr| chr == '\n')
-    }
+    /// The syntax errors in the document, in order. They are kept on the nodes, so only the
+    /// parts of the tree with errors in them are looked at.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut todo = vec![&self.root];
+
+        while let Some(node) = todo.pop() {
+            if let Some(error) = &node.error {
+                diagnostics.push(Diagnostic {
+                    span: node.span,
+                    severity: Severity::Error,
+                    message: error.message.clone(),
+                    expected: error.expected.clone(),
+                });
+            }
 
-    /// Get the byte length of the text.
-    pub fn byte_len(&self) -> usize {
-        self.0.as_bytes().len()
-    }
+            todo.extend(node.subs.iter().rev().filter(|sub| sub.error_count > 0));
+        }
 
-    ///
-    pub fn chars_indices<'a>(&'a self) -> CharIndices<'a> {
-        self.0.char_indices()
+        return diagnostics;
     }
 }
-
@@ -2,12 +2,15 @@ use crate::document::{Node, Document};
 use std::rc::Rc;
 
 /// A step along the iteration of the nodes in a document.
+#[derive(Clone)]
 struct NodeIterStep<'a, 'b> {
     node: &'b Rc<Node<'a>>,
     index: usize,
 }
 
-/// An iterator of the nodes in a document.
+/// An iterator of the nodes in a document. Parents come before their children, so the nodes
+/// are in order of where they start.
+#[derive(Clone)]
 pub struct NodeIter<'a, 'b> {
     nodes: Vec<NodeIterStep<'a, 'b>>,
 }
@@ -15,7 +18,7 @@ pub struct NodeIter<'a, 'b> {
 impl<'a, 'b> NodeIter<'a, 'b> {
     /// Initializes a new node iter.
     pub fn new(document: &'b Document<'a>) -> NodeIter<'a, 'b> {
-        if document.root.subs.len() == 0 {
+        if document.root.subs.is_empty() {
             return NodeIter { nodes: vec![] };
         }
 
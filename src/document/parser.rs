@@ -1,16 +1,54 @@
-use crate::document::{Document, NodeIter, Span, Node};
+use crate::document::{Document, Expected, Kind, NodeIter, Span, Node, State, SyntaxError};
+use crate::document::diagnostic::describe;
 use crate::rules::Rule;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::rc::Rc;
 
-/// Return a new node tree for the given document.
-pub fn parse<'a, 'b>(document: &'b Document<'a>, edit: Span) -> Rc<Node<'a>> {
+/// Return a new node tree for the given document, and statistics about the parse.
+pub fn parse<'a, 'b>(document: &'b Document<'a>, edit: Span) -> (Rc<Node<'a>>, ParseStats) {
     let mut parser = Parser {
         edit, document,
         node: document.node_iter(),
         offset: 0,
+        reach: Cell::new(0),
+        state: Rc::new(State::default()),
+        rule: 0,
+        arg: 0,
+        memo: HashMap::new(),
+        log: vec![],
+        farthest: 0,
+        expected: vec![],
+        stats: ParseStats::default(),
     };
 
-    return parser.parse(0).unwrap();
+    let root = parser.parse_file();
+
+    return (root, parser.stats);
+}
+
+/// Statistics about a single parse.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ParseStats {
+    /// Times a rule at an offset was found in the memo table.
+    pub hits: usize,
+
+    /// Times a rule at an offset was not in the memo table.
+    pub misses: usize,
+
+    /// Of the misses, how many reused a node from the previous tree.
+    pub reused: usize,
+}
+
+impl ParseStats {
+    /// The fraction of lookups that were found in the memo table.
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            return 0.0;
+        }
+
+        return self.hits as f64 / (self.hits + self.misses) as f64;
+    }
 }
 
 /// Updates the parse tree for a document.
@@ -18,6 +56,12 @@ pub struct Parser<'a, 'b> {
     /// The current position in the text in bytes.
     pub offset: usize,
 
+    /// The end of the text looked at by the rule being parsed so far, including its children.
+    reach: Cell<usize>,
+
+    /// The current state of the parser, like the lexer mode.
+    state: Rc<State>,
+
     /// The document we want to update.
     document: &'b Document<'a>,
 
@@ -26,25 +70,82 @@ pub struct Parser<'a, 'b> {
 
     /// An iterator of nodes to find memorized nodes.
     node: NodeIter<'a, 'b>,
+
+    /// The index of the rule currently being parsed.
+    rule: usize,
+
+    /// The argument the current rule was parsed with.
+    arg: u32,
+
+    /// What we know about each rule at each offset during this parse.
+    memo: HashMap<Key, Memo<'a>>,
+
+    /// The keys of the finished memo entries, in the order they were added.
+    log: Vec<Key>,
+
+    /// The farthest offset something was expected at but wasn't found.
+    farthest: usize,
+
+    /// What was expected at the farthest offset.
+    expected: Vec<Expected>,
+
+    /// Statistics about this parse.
+    stats: ParseStats,
+}
+
+/// A rule with an argument at an offset, in a state.
+type Key = (usize, u32, usize, Rc<State>);
+
+/// A place to go back to, if a rule doesn't work out.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    /// The offset in the text.
+    offset: usize,
+
+    /// The state of the parser.
+    state: Rc<State>,
+}
+
+impl Checkpoint {
+    /// The offset in the text.
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+}
+
+/// What we know about a rule at an offset.
+enum Memo<'a> {
+    /// The rule is being parsed here. If it's reached again before it's done it is left
+    /// recursive, and the seed is used instead of recursing forever.
+    Growing {
+        /// The best result found so far. Starts out as a failure.
+        seed: Option<Rc<Node<'a>>>,
+
+        /// Has the rule been reached again while being parsed?
+        recursive: bool,
+    },
+
+    /// The rule has been parsed here, and either failed or created the node. Either way, it
+    /// looked at the text up to the offset, and expected what it did at the farthest offset.
+    Done(Option<Rc<Node<'a>>>, usize, (usize, Vec<Expected>)),
 }
 
 impl<'a, 'b> Parser<'a, 'b> {
-    fn get_node(&mut self, rule: &'a Box<dyn Rule>, index: usize) -> Option<Rc<Node<'a>>> {
-        while let Some(node) = self.node.peek() {
-            if node.span.0 < index {
-                self.node.next();
-                continue;
-            }
+    fn get_node(&mut self, rule: &'a Box<dyn Rule>, arg: u32, index: usize) -> Option<Rc<Node<'a>>> {
+        while self.node.peek().map_or(false, |node| node.span.0 < index) {
+            self.node.next();
+        }
 
-            let right_index = node.span.0 == index;
-            let right_rule = node.rule == rule;
-            let unedited = self.edit.1 < node.span.0 || self.edit.0 > node.span.1;
+        // Several nodes can start here, like a parent and its first child, or empty nodes.
+        for node in self.node.clone().take_while(|node| node.span.0 == index) {
+            let right_rule = node.rule == rule && node.arg == arg && !node.nested;
+            let right_state = node.state.0 == self.state;
+            let unedited = self.edit.1 < node.span.0 || self.edit.0 > node.lookahead;
 
-            return if right_index && right_rule && unedited {
-                Some(node.clone())
-            } else {
-                None
-            };
+            // Recovered nodes were made up to get past an error, not made by the rule.
+            if right_rule && right_state && unedited && !node.recovered {
+                return Some(node.clone());
+            }
         }
 
         return None;
@@ -52,11 +153,36 @@ impl<'a, 'b> Parser<'a, 'b> {
 }
 
 impl<'a> Parser<'a, '_> {
+    /// Look at the character at the current offset.
+    pub fn peek(&self) -> Option<char> {
+        let chr = self.document.text.read(self.offset);
+        self.look(self.offset + chr.map_or(0, |chr| chr.len_utf8()));
+
+        return chr;
+    }
+
+    /// Note that the text up to the offset was looked at.
+    fn look(&self, offset: usize) {
+        if offset > self.reach.get() {
+            self.reach.set(offset);
+        }
+    }
+
+    /// The text a node covers.
+    pub fn text(&self, node: &Node) -> String {
+        return self.document.text.slice(node.span);
+    }
+
     /// Eats a character if it matches the given func.
     pub fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> bool {
-        if let Some(chr) = self.document.text.read(self.offset) {
+        // Nothing can be read until the indents or dedents of the line have been matched.
+        if self.state.pending.is_some() {
+            return false;
+        }
+
+        if let Some(chr) = self.peek() {
             if func(&chr) {
-                self.offset += 1;
+                self.offset += chr.len_utf8();
                 return true;
             }
         }
@@ -65,37 +191,434 @@ impl<'a> Parser<'a, '_> {
     }
 
     /// Eat as long as a Rule matches.
-    pub fn parse(&mut self, rule: usize) -> Option<Rc<Node<'a>>> {
-        let rule = &self.document.lang[rule];
+    pub fn parse(&mut self, index: usize) -> Option<Rc<Node<'a>>> {
+        return self.parse_with(index, 0);
+    }
+
+    /// Eat as long as a Rule matches, passing it an argument. The same rule with different
+    /// arguments is memorized and reused separately.
+    pub fn parse_with(&mut self, index: usize, arg: u32) -> Option<Rc<Node<'a>>> {
+        let rule = &self.document.lang.rules[index];
+        let key = (index, arg, self.offset, self.state.clone());
+
+        // If we are already parsing this rule here, then it's left recursive. Use the seed.
+        if let Some(Memo::Growing { seed, recursive }) = self.memo.get_mut(&key) {
+            *recursive = true;
+
+            let seed = seed.clone();
+            if let Some(node) = &seed {
+                self.look(node.lookahead);
+                self.skip(node);
+            }
+
+            return seed;
+        }
+
+        // Check to see if we already parsed this rule here.
+        if let Some(Memo::Done(result, lookahead, expected)) = self.memo.get(&key) {
+            self.stats.hits += 1;
+            self.look(*lookahead);
+
+            let (result, expected) = (result.clone(), expected.clone());
+            self.expect_at(expected.0, expected.1);
+
+            if let Some(node) = &result {
+                self.skip(node);
+            }
+
+            return result;
+        }
+
+        self.stats.misses += 1;
 
         // Check to see if we have this one memorized.
-        if let Some(node) = self.get_node(rule, self.offset) {
+        if let Some(node) = self.get_node(rule, arg, self.offset) {
+            self.stats.reused += 1;
+            self.look(node.lookahead);
+            self.expect_at(node.expected.0, node.expected.1.clone());
+
             // If we do have one, then skip the cursor past it.
-            self.offset = node.span.1;
+            self.skip(&node);
 
             // Then return the old node.
             return Some(node.clone());
         }
 
-        // Keep a copy of the old offset
-        let offset = self.offset;
+        // Keep a copy of where we started
+        let start = self.save();
+        let log = self.log.len();
 
-        // Try to parse the rule
-        if let Some((kind, subs)) = rule.parse(self) {
-            if offset == self.offset {
-                return None;
+        // Find out how far this rule looks, and what it expects, apart from the rule that is
+        // parsing it.
+        let reach = self.reach.replace(self.offset);
+        let farthest = std::mem::replace(&mut self.farthest, self.offset);
+        let expected = std::mem::take(&mut self.expected);
+
+        self.memo.insert(key.clone(), Memo::Growing { seed: None, recursive: false });
+
+        let mut result = self.apply(index, arg);
+
+        // If the rule reached itself, grow the seed by parsing again until it stops getting longer.
+        if let Some(Memo::Growing { recursive: true, .. }) = self.memo.get(&key) {
+            while let Some(seed) = result.clone() {
+                // Anything parsed since we started may depend on the old seed, so forget it.
+                for key in self.log.drain(log..) {
+                    self.memo.remove(&key);
+                }
+
+                // From here on the seed is only a part of the result, so it's never reused.
+                let seed = Rc::new(Node { nested: true, ..(*seed).clone() });
+
+                self.memo.insert(key.clone(), Memo::Growing { seed: Some(seed.clone()), recursive: true });
+                self.restore(start.clone());
+
+                match self.apply(index, arg) {
+                    Some(grown) if grown.span.1 > seed.span.1 => result = Some(grown),
+                    _ => {
+                        self.skip(&seed);
+                        break;
+                    },
+                }
+            }
+
+            // The last try at growing looked past the result too.
+            let lookahead = self.reach.get();
+            result = result.map(|node| Rc::new(Node {
+                lookahead: node.lookahead.max(lookahead),
+                expected: (self.farthest, self.expected.clone()),
+                ..(*node).clone()
+            }));
+        }
+
+        let lookahead = self.reach.get();
+        self.reach.set(reach.max(lookahead));
+
+        // The rule parsing this one expected everything this one did too.
+        let inner = (std::mem::replace(&mut self.farthest, farthest), std::mem::replace(&mut self.expected, expected));
+        self.expect_at(inner.0, inner.1.clone());
+
+        self.memo.insert(key.clone(), Memo::Done(result.clone(), lookahead, inner));
+        self.log.push(key);
+
+        return result;
+    }
+
+    /// Parse the file rule over the whole text. Text the file rule can't parse is skipped
+    /// into error nodes, and the file rule is started again after it.
+    fn parse_file(&mut self) -> Rc<Node<'a>> {
+        let mut subs = vec![];
+
+        loop {
+            let here = self.save();
+
+            // An empty file node doesn't get us anywhere, unless there is nothing left anyway.
+            match self.parse(0) {
+                Some(node) if subs.is_empty() && self.at_end() => return node,
+                Some(node) if self.offset > here.offset || self.at_end() => subs.extend(node.subs.iter().cloned()),
+                _ => self.restore(here),
+            }
+
+            if self.at_end() {
+                break;
+            }
+
+            let start = self.save();
+
+            // Trying the file rule along the way shouldn't change what the error says.
+            let failure = (self.farthest, self.expected.clone());
+
+            while !self.at_end() {
+                // An indentation change that nothing used would stop us here for good.
+                if !self.next_if(|_| true) {
+                    self.state_mut().pending = None;
+                    continue;
+                }
+
+                let here = self.save();
+                let parsed = self.parse(0).map_or(false, |node| node.span.1 > node.span.0);
+                self.restore(here);
+
+                if parsed {
+                    break;
+                }
             }
 
+            self.farthest = failure.0;
+            self.expected = failure.1;
+
+            subs.push(self.error(&start, vec![]));
+        }
+
+        return Rc::new(Node {
+            span: (0, self.offset),
+            lookahead: self.reach.get().max(self.offset),
+            state: (Rc::default(), self.state.clone()),
+            rule: &self.document.lang.rules[0],
+            arg: 0,
+            kind: Kind::FILE,
+            error: None,
+            error_count: Node::count_errors(&None, &subs),
+            expected: (0, vec![]),
+            recovered: false,
+            nested: false,
+            subs,
+        });
+    }
+
+    /// Run a Rule at the current offset, without checking for memorized nodes.
+    fn apply(&mut self, index: usize, arg: u32) -> Option<Rc<Node<'a>>> {
+        let rule = &self.document.lang.rules[index];
+
+        // Keep a copy of where we started
+        let start = self.save();
+
+        // Try to parse the rule
+        let parent = std::mem::replace(&mut self.rule, index);
+        let parent_arg = std::mem::replace(&mut self.arg, arg);
+        let result = rule.parse(self);
+        self.rule = parent;
+        self.arg = parent_arg;
+
+        if let Some((kind, subs)) = result {
+            // Rules can give up on the text by making an error node themselves.
+            let error = if kind == Kind::ERROR { Some(self.syntax_error(start.offset)) } else { None };
+
             return Some(Rc::new(Node {
-                span: (offset, self.offset),
-                subs, kind, rule,
+                span: (start.offset, self.offset),
+                lookahead: self.reach.get().max(self.offset),
+                state: (start.state, self.state.clone()),
+                error_count: Node::count_errors(&error, &subs),
+                expected: (self.farthest, self.expected.clone()),
+                recovered: false,
+                nested: false,
+                subs, kind, rule, arg, error,
             }));
         }
 
-        // We have failed :(. Return the offset to the original value.
-        self.offset = offset;
+        // We have failed :(. Go back to where we started.
+        self.restore(start);
+
+        return None;
+    }
+
+    /// Create a node for the rule being parsed, spanning the given children. This is for
+    /// rules that build nested nodes of their own. The node is only a part of the rule's
+    /// result, so it is never reused on its own.
+    pub fn node(&self, kind: Kind, subs: Vec<Rc<Node<'a>>>) -> Rc<Node<'a>> {
+        let start = subs.first().map_or((self.offset, &self.state), |node| (node.span.0, &node.state.0));
+        let end = subs.last().map_or((self.offset, &self.state), |node| (node.span.1, &node.state.1));
+
+        return Rc::new(Node {
+            span: (start.0, end.0),
+            lookahead: subs.iter().map(|node| node.lookahead).max().unwrap_or(end.0).max(end.0),
+            state: (start.1.clone(), end.1.clone()),
+            rule: &self.document.lang.rules[self.rule],
+            arg: self.arg,
+            error: None,
+            error_count: Node::count_errors(&None, &subs),
+            expected: (0, vec![]),
+            recovered: false,
+            nested: true,
+            kind, subs,
+        });
+    }
+
+    /// Create an error node for the rule being parsed, covering the text skipped since the
+    /// checkpoint. The children are the nodes found in the skipped text, if any.
+    pub fn error(&self, start: &Checkpoint, subs: Vec<Rc<Node<'a>>>) -> Rc<Node<'a>> {
+        let error = Some(self.syntax_error(start.offset));
+
+        return Rc::new(Node {
+            span: (start.offset, self.offset),
+            lookahead: self.reach.get().max(self.offset),
+            state: (start.state.clone(), self.state.clone()),
+            rule: &self.document.lang.rules[self.rule],
+            arg: self.arg,
+            kind: Kind::ERROR,
+            error_count: Node::count_errors(&error, &subs),
+            expected: (0, vec![]),
+            recovered: true,
+            nested: false,
+            subs, error,
+        });
+    }
+
+    /// Create an empty error node for the rule being parsed, saying what is wrong here. Like
+    /// the nodes from `node`, it is a part of the rule's result.
+    pub fn report(&self, message: &str) -> Rc<Node<'a>> {
+        return Rc::new(Node {
+            span: (self.offset, self.offset),
+            lookahead: self.offset,
+            state: (self.state.clone(), self.state.clone()),
+            rule: &self.document.lang.rules[self.rule],
+            arg: self.arg,
+            kind: Kind::ERROR,
+            subs: vec![],
+            error: Some(Rc::new(SyntaxError { message: message.to_string(), expected: vec![] })),
+            error_count: 1,
+            expected: (0, vec![]),
+            recovered: false,
+            nested: true,
+        });
+    }
+
+    /// Create an empty node, standing in for a node of the rule and kind that should have
+    /// been here but wasn't. Expected is what the node would have been.
+    pub fn missing(&self, rule: usize, kind: Kind, expected: Vec<Expected>) -> Rc<Node<'a>> {
+        let message = format!("missing {}", describe(&expected, &self.document.lang.kinds));
+
+        return Rc::new(Node {
+            span: (self.offset, self.offset),
+            lookahead: self.offset,
+            state: (self.state.clone(), self.state.clone()),
+            rule: &self.document.lang.rules[rule],
+            arg: 0,
+            kind,
+            subs: vec![],
+            error: Some(Rc::new(SyntaxError { message, expected })),
+            error_count: 1,
+            expected: (0, vec![]),
+            recovered: true,
+            nested: false,
+        });
+    }
+
+    /// Note that something was expected here, but wasn't found. Only what was expected at the
+    /// farthest offset is kept, as that is usually where the mistake is.
+    pub fn expect(&mut self, expected: Vec<Expected>) {
+        self.expect_at(self.offset, expected);
+    }
+
+    /// Note that something was expected at the offset, but wasn't found.
+    fn expect_at(&mut self, offset: usize, expected: Vec<Expected>) {
+        if expected.is_empty() {
+            return;
+        }
+
+        if offset > self.farthest {
+            self.farthest = offset;
+            self.expected.clear();
+        }
+
+        if offset == self.farthest {
+            for expected in expected {
+                if !self.expected.contains(&expected) {
+                    self.expected.push(expected);
+                }
+            }
+        }
+    }
+
+    /// Describe the error in text that couldn't be parsed, from the start offset to here.
+    fn syntax_error(&self, start: usize) -> Rc<SyntaxError> {
+        // The farthest failure is only about this text if it happened inside of it.
+        if self.expected.is_empty() || self.farthest < start || self.farthest > self.offset {
+            return Rc::new(SyntaxError { message: "unexpected text".to_string(), expected: vec![] });
+        }
+
+        return Rc::new(SyntaxError {
+            message: format!("expected {}", describe(&self.expected, &self.document.lang.kinds)),
+            expected: self.expected.clone(),
+        });
+    }
+
+    /// Is the rule a predicate?
+    pub fn is_predicate(&self, index: usize) -> bool {
+        return self.document.lang.rules[index].predicate();
+    }
+
+    /// Could the rule match at the current offset, going by the characters it can start with?
+    pub fn can_start(&self, index: usize) -> bool {
+        let next = self.peek();
+
+        return self.document.lang.analysis.first[index].can_start(next);
+    }
+
+    /// Check if a predicate rule matches here. Nothing is consumed. The result is memorized
+    /// like any other rule's.
+    pub fn test(&mut self, index: usize) -> bool {
+        let start = self.save();
+        let matched = self.parse(index).is_some();
+        self.restore(start);
+
+        return matched;
+    }
+
+    /// Is the parser at the end of the text?
+    pub fn at_end(&self) -> bool {
+        self.look(self.offset);
+
+        return self.offset >= self.document.text.byte_len();
+    }
+
+    /// Eat a Rule, but only if it creates a node of the given kind.
+    pub fn parse_kind(&mut self, rule: usize, kind: Kind) -> Option<Rc<Node<'a>>> {
+        let start = self.save();
+
+        if let Some(node) = self.parse(rule) {
+            if node.kind == kind {
+                return Some(node);
+            }
+        }
+
+        self.restore(start);
 
         return None;
     }
-}
 
+    /// The index of the rule being parsed.
+    pub fn rule(&self) -> usize {
+        return self.rule;
+    }
+
+    /// The argument the rule being parsed was given.
+    pub fn arg(&self) -> u32 {
+        return self.arg;
+    }
+
+    /// The current state of the parser.
+    pub fn state(&self) -> &State {
+        return &self.state;
+    }
+
+    /// Change the state of the parser.
+    pub fn state_mut(&mut self) -> &mut State {
+        return Rc::make_mut(&mut self.state);
+    }
+
+    /// Enter a lexer mode.
+    pub fn push_mode(&mut self, mode: usize) {
+        self.state_mut().modes.push(mode);
+    }
+
+    /// Leave the current lexer mode, going back to the one before it.
+    pub fn pop_mode(&mut self) {
+        self.state_mut().modes.pop();
+    }
+
+    /// Remember where the parser is, so it can come back if a rule doesn't work out.
+    pub fn save(&self) -> Checkpoint {
+        return Checkpoint {
+            offset: self.offset,
+            state: self.state.clone(),
+        };
+    }
+
+    /// Go back to a saved place.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.offset = checkpoint.offset;
+        self.state = checkpoint.state;
+    }
+
+    /// Go back to the start of a node, undoing everything since.
+    pub fn rewind(&mut self, node: &Node<'a>) {
+        self.offset = node.span.0;
+        self.state = node.state.0.clone();
+    }
+
+    /// Move past the end of a node.
+    pub fn skip(&mut self, node: &Node<'a>) {
+        self.offset = node.span.1;
+        self.state = node.state.1.clone();
+    }
+}
@@ -0,0 +1,27 @@
+/// What the parser knows about where it is, other than the offset. A node can only be reused
+/// when the parser is in the same state it was made in.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct State {
+    /// The stack of lexer modes that have been entered. The bottom mode, 0, is always there.
+    pub modes: Vec<usize>,
+
+    /// The indentation widths of the blocks the parser is in, innermost last.
+    pub indents: Vec<usize>,
+
+    /// The indentation width of the line the parser is at the start of, if it still needs
+    /// indents or dedents. Nothing else is read until they're matched, so it's always about
+    /// the current offset, and stays right when an edit moves the nodes it's kept on.
+    pub pending: Option<usize>,
+}
+
+impl State {
+    /// The lexer mode on top of the stack.
+    pub fn mode(&self) -> usize {
+        return self.modes.last().copied().unwrap_or(0);
+    }
+
+    /// The indentation width of the innermost block.
+    pub fn indent(&self) -> usize {
+        return self.indents.last().copied().unwrap_or(0);
+    }
+}
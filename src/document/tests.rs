@@ -0,0 +1,424 @@
+use std::rc::Rc;
+use crate::document::{Document, Kind, Node, ParseStats};
+use crate::rules::*;
+
+/// Write out the kinds and spans of a tree, one node per line.
+fn tree(document: &Document, node: &Rc<Node>, depth: usize, out: &mut String) {
+    out.push_str(&format!("{}{} {:?}\n", "  ".repeat(depth), document.lang.kinds.name(node.kind), node.span));
+
+    for sub in &node.subs {
+        tree(document, sub, depth + 1, out);
+    }
+}
+
+/// Parse the text, make the edit, and check that the tree is the same as parsing the edited
+/// text from scratch. Returns how many nodes were reused.
+fn reparse(language: &Language, text: &str, span: (usize, usize), edit: &str) -> usize {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+    document.edit(span, edit);
+
+    let mut fresh = Document::new(language);
+    fresh.edit((0, 0), &document.text.to_string());
+
+    let (mut edited, mut expected) = (String::new(), String::new());
+    tree(&document, &document.root, 0, &mut edited);
+    tree(&fresh, &fresh.root, 0, &mut expected);
+    assert_eq!(edited, expected);
+
+    return document.stats.reused;
+}
+
+/// Numbers with `+` at precedence 1 and `*` at precedence 2.
+fn arithmetic() -> Language {
+    let mut language = LanguageBuilder::new();
+
+    let number = language.kind("Number");
+    let plus = language.kind("Plus");
+    let star = language.kind("Star");
+    let add = language.kind("Add");
+    let mul = language.kind("Mul");
+
+    language.rule("File", Automata::new(vec![
+        Step(vec![(("Expr", add), 1), (("Expr", mul), 1), (("Expr", number), 1)], None),
+        Step(vec![], Some(Kind::FILE)),
+    ]));
+
+    language.rule("Expr", Pratt::new(vec![("Lexer", number)], "Lexer", vec![
+        Operator::infix(plus, 1, Associativity::Left, add),
+        Operator::infix(star, 2, Associativity::Left, mul),
+    ]));
+
+    language.rule("Lexer", Lexer::new(vec![
+        Step(vec![(CharClass::Range('0', '9'), 1), (CharClass::char('+'), 2), (CharClass::char('*'), 3)], None),
+        Step(vec![(CharClass::Range('0', '9'), 1)], Some(number)),
+        Step(vec![], Some(plus)),
+        Step(vec![], Some(star)),
+    ]));
+
+    return language.build().ok().unwrap();
+}
+
+#[test]
+fn pratt_operations_are_not_reused_as_results() {
+    let language = arithmetic();
+
+    reparse(&language, "1*2+3", (4, 5), "4");
+    reparse(&language, "1+2*3", (0, 1), "4");
+    reparse(&language, "1*2*3+4", (6, 7), "5*6");
+}
+
+#[test]
+fn pratt_operands_are_reused() {
+    let language = arithmetic();
+
+    // The right of the `+` is parsed as the rule again, so it is reused whole. The `+` touches
+    // the edit, so it is parsed again.
+    assert_eq!(reparse(&language, "1+2*3", (0, 1), "4"), 1);
+    assert_eq!(reparse(&language, "1+2*3*4", (0, 1), "5"), 1);
+}
+
+#[test]
+fn memo_hits_and_misses() {
+    let language = arithmetic();
+
+    let mut document = Document::new(&language);
+    assert_eq!(document.stats.hit_rate(), 0.0);
+
+    // The file tries the expression for each kind it takes, and the expression reads the
+    // number as a prefix operator first, but each is only parsed once.
+    document.edit((0, 0), "1");
+    assert_eq!(document.stats, ParseStats { hits: 3, misses: 4, reused: 0 });
+    assert_eq!(document.stats.hit_rate(), 3.0 / 7.0);
+
+    // Of the misses, only the operand after the `+` is reused.
+    document.edit((1, 1), "+2");
+    document.edit((0, 1), "3");
+    assert_eq!(document.stats.reused, 1);
+}
+
+#[test]
+fn tokens_are_parsed_again_when_text_they_looked_past_changes() {
+    let mut language = LanguageBuilder::new();
+
+    let number = language.kind("Number");
+    let dot = language.kind("Dot");
+    let name = language.kind("Name");
+
+    language.rule("File", Automata::new(vec![
+        Step(vec![(("Lexer", number), 0), (("Lexer", dot), 0), (("Lexer", name), 0)], Some(Kind::FILE)),
+    ]));
+
+    language.rule("Lexer", Lexer::from_regex(vec![
+        (r"[0-9]+(\.[0-9]+)?", number),
+        (r"\.", dot),
+        ("[a-z]+", name),
+    ]).unwrap());
+
+    let language = language.build().ok().unwrap();
+
+    // The `1` looked at the `.` and the `x` to find out it wasn't `1.5`.
+    reparse(&language, "1.x", (2, 3), "5");
+    reparse(&language, "1.xy", (2, 3), "5");
+    reparse(&language, "12.5", (3, 4), "y");
+}
+
+#[test]
+fn predicates_are_parsed_again_when_text_they_looked_at_changes() {
+    let mut language = LanguageBuilder::new();
+
+    let name = language.kind("Name");
+    let space = language.kind("Space");
+    let equal = language.kind("Equal");
+    let statement = language.kind("Statement");
+
+    // A name is a statement, unless it's followed by `=`.
+    language.rule("File", Automata::new(vec![
+        Step(vec![(("Statement", statement), 0), (("Lexer", name), 0), (("Lexer", space), 0), (("Lexer", equal), 0)], Some(Kind::FILE)),
+    ]));
+
+    language.rule("Statement", Automata::new(vec![
+        Step(vec![(("Lexer", name), 1)], None),
+        Step(vec![(("NotAssign", statement), 2)], None),
+        Step(vec![], Some(statement)),
+    ]));
+
+    language.rule("NotAssign", Not::new(("Assign", statement)));
+
+    language.rule("Assign", Automata::new(vec![
+        Step(vec![(("Lexer", space), 1), (("Lexer", equal), 2)], None),
+        Step(vec![(("Lexer", equal), 2)], None),
+        Step(vec![], Some(statement)),
+    ]));
+
+    language.rule("Lexer", Lexer::from_regex(vec![("[a-z]+", name), (" +", space), ("=", equal)]).unwrap());
+
+    let language = language.build().ok().unwrap();
+
+    reparse(&language, "x   y", (4, 5), "=");
+    reparse(&language, "x   =", (4, 5), "y");
+    reparse(&language, "x y", (1, 2), "=");
+}
+
+/// A name, which is a call if the next thing after any spaces is `(`. Only the name is in
+/// the token.
+struct Callee {
+    /// The kind of a name on its own.
+    name: Kind,
+
+    /// The kind of a name that is called.
+    call: Kind,
+}
+
+impl Scanner for Callee {
+    fn scan(&self, scan: &mut Scan) -> Option<Kind> {
+        if !scan.peek()?.is_ascii_lowercase() {
+            return None;
+        }
+
+        while scan.peek().map_or(false, |chr| chr.is_ascii_lowercase()) {
+            scan.advance();
+        }
+
+        scan.mark_end();
+
+        while scan.peek() == Some(' ') {
+            scan.advance();
+        }
+
+        return Some(if scan.peek() == Some('(') { self.call } else { self.name });
+    }
+}
+
+/// Nested comments, raw strings, names and calls, spaces and `(`.
+fn scanned() -> Language {
+    let mut language = LanguageBuilder::new();
+
+    let comment = language.kind("Comment");
+    let raw = language.kind("Raw");
+    let name = language.kind("Name");
+    let call = language.kind("Call");
+    let space = language.kind("Space");
+    let open = language.kind("Open");
+
+    language.rule("File", Automata::new(vec![
+        Step(vec![
+            (("Comment", comment), 0),
+            (("Raw", raw), 0),
+            (("Callee", name), 0),
+            (("Callee", call), 0),
+            (("Lexer", space), 0),
+            (("Lexer", open), 0),
+        ], Some(Kind::FILE)),
+    ]));
+
+    language.rule("Comment", External::new(NestedComment::new(comment)));
+    language.rule("Raw", External::new(RawString::new(raw)));
+    language.rule("Callee", External::new(Callee { name, call }));
+
+    language.rule("Lexer", Lexer::new(vec![
+        Step(vec![(CharClass::char(' '), 1), (CharClass::char('('), 2)], None),
+        Step(vec![(CharClass::char(' '), 1)], Some(space)),
+        Step(vec![], Some(open)),
+    ]));
+
+    return language.build().ok().unwrap();
+}
+
+/// Parse the text, and write out its tree.
+fn scan(language: &Language, text: &str) -> String {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+
+    let mut out = String::new();
+    tree(&document, &document.root, 0, &mut out);
+
+    return out;
+}
+
+#[test]
+fn nested_comments() {
+    let language = scanned();
+
+    assert_eq!(scan(&language, "/* a /* b */ c */"), "File (0, 17)\n  Comment (0, 17)\n");
+
+    // Without its last `*/` the comment is unclosed, so it isn't one. The one inside it is.
+    assert!(scan(&language, "/* a /* b */").starts_with("File (0, 12)\n  Error (0, 2)\n"));
+    assert!(scan(&language, "/* a /* b */").ends_with("  Comment (5, 12)\n"));
+}
+
+#[test]
+fn raw_strings() {
+    let language = scanned();
+
+    assert_eq!(scan(&language, r##"r#"say "hi""#"##), "File (0, 13)\n  Raw (0, 13)\n");
+
+    // A quote with too few `#` after it doesn't end the string.
+    assert_eq!(scan(&language, r###"r##"a"#b"##"###), "File (0, 11)\n  Raw (0, 11)\n");
+    assert!(!scan(&language, r##"r##"a"#"##).contains("Raw"));
+}
+
+#[test]
+fn scanners_look_past_the_end_they_mark() {
+    let language = scanned();
+
+    let mut document = Document::new(&language);
+    document.edit((0, 0), "f  (");
+
+    let call = &document.root.subs[0];
+    assert_eq!(document.lang.kinds.name(call.kind), "Call");
+    assert_eq!((call.span, call.lookahead), ((0, 1), 4));
+
+    // The token is read again when what it looked at changes, and reused when it doesn't.
+    reparse(&language, "f  (", (3, 4), "x");
+    reparse(&language, "f  x", (3, 4), "(");
+    assert!(reparse(&language, "f  (gg", (5, 6), "h") > 0);
+}
+
+/// Statements that are a name on a line, or a name and `:` with a block of statements under
+/// it. The file can be a block of its own, if the first line is indented.
+fn blocks() -> Language {
+    let mut language = LanguageBuilder::new();
+
+    let name = language.kind("Name");
+    let colon = language.kind("Colon");
+    let newline = language.kind("Newline");
+    let indent = language.kind("Indent");
+    let dedent = language.kind("Dedent");
+    let statement = language.kind("Statement");
+
+    language.rule("File", Automata::new(vec![
+        Step(vec![(("Statement", statement), 0), (("Layout", newline), 1)], Some(Kind::FILE)),
+        Step(vec![(("Layout", indent), 2), (("Statement", statement), 0)], None),
+        Step(vec![(("Statement", statement), 2), (("Layout", dedent), 0)], None),
+    ]));
+
+    language.rule("Statement", Automata::new(vec![
+        Step(vec![(("Lexer", name), 1)], None),
+        Step(vec![(("Lexer", colon), 3), (("Layout", newline), 2)], None),
+        Step(vec![], Some(statement)),
+        Step(vec![(("Layout", newline), 4)], None),
+        Step(vec![(("Layout", indent), 5)], None),
+        Step(vec![(("Statement", statement), 5), (("Layout", dedent), 2)], None),
+    ]));
+
+    language.rule("Layout", Layout::new(newline, indent, dedent, LayoutOptions::default()));
+
+    language.rule("Lexer", Lexer::new(vec![
+        Step(vec![(CharClass::Range('a', 'z'), 1), (CharClass::char(':'), 2)], None),
+        Step(vec![(CharClass::Range('a', 'z'), 1)], Some(name)),
+        Step(vec![], Some(colon)),
+    ]));
+
+    return language.build().ok().unwrap();
+}
+
+#[test]
+fn dedents_to_a_width_never_opened_are_errors() {
+    let language = blocks();
+
+    let mut document = Document::new(&language);
+    document.edit((0, 0), "a:\n    b\n  c\n");
+
+    let diagnostics = document.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].span, (11, 11));
+    assert_eq!(diagnostics[0].message, "the indentation doesn't match any block");
+
+    // The line is in the outer block, rather than starting a block of its own.
+    let mut out = String::new();
+    tree(&document, &document.root, 0, &mut out);
+    assert!(!out.contains("Indent (11, 11)"));
+
+    reparse(&language, "a:\n    b\n    c\n", (9, 11), "");
+}
+
+#[test]
+fn the_first_line_is_measured() {
+    let language = blocks();
+
+    let mut document = Document::new(&language);
+    document.edit((0, 0), "  a\n  b\n");
+
+    let mut out = String::new();
+    tree(&document, &document.root, 0, &mut out);
+    assert_eq!(out, [
+        "File (0, 8)",
+        "  Newline (0, 2)",
+        "  Indent (2, 2)",
+        "  Statement (2, 6)",
+        "    Name (2, 3)",
+        "    Newline (3, 6)",
+        "  Statement (6, 8)",
+        "    Name (6, 7)",
+        "    Newline (7, 8)",
+        "  Dedent (8, 8)",
+        "",
+    ].join("\n"));
+    assert_eq!(document.diagnostics().len(), 0);
+
+    reparse(&language, "a\n", (0, 0), "  ");
+    reparse(&language, "  a\n", (0, 2), "");
+}
+
+#[test]
+fn layout_nodes_moved_by_an_edit() {
+    let language = blocks();
+
+    // The statements after the edit are reused, with the blocks still to close at their end.
+    reparse(&language, "\tb\nb", (0, 0), " \n");
+    reparse(&language, "a:\n  b\nc", (0, 0), "d\n");
+    reparse(&language, "a:\n  b\n    c\nd", (0, 1), "e");
+}
+
+
+
+#[test]
+fn nodes_after_empty_ones_are_reused() {
+    let language = blocks();
+
+    // The inner statement starts at the same offset as the empty indent in front of it. It's
+    // reused along with the name, colon, newline and indent before the edit, and the newline
+    // after it.
+    assert_eq!(reparse(&language, "a:\n  b\n  c\n", (9, 10), "d"), 6);
+}
+
+/// Parse the text, move the nodes as if the text from the start was replaced, and write out
+/// the moved tree.
+fn moved(language: &Language, text: &str, start: usize, removed: usize, added: usize) -> String {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+
+    let mut root = std::mem::replace(&mut document.root, Document::new(language).root);
+    super::incrament_node(&mut root, removed, added, start);
+
+    let mut out = String::new();
+    tree(&document, &root, 0, &mut out);
+
+    return out;
+}
+
+#[test]
+fn nodes_are_moved_around_an_edit() {
+    let language = blocks();
+
+    // Offsets up to the edit stay put, so the empty indent stays in front of the new text.
+    assert!(moved(&language, "a:\n  b\n", 5, 1, 2).contains([
+        "    Newline (2, 5)",
+        "    Indent (5, 5)",
+        "    Statement (5, 8)",
+        "      Name (5, 7)",
+        "      Newline (7, 8)",
+        "    Dedent (8, 8)",
+    ].join("\n").as_str()));
+
+    // Offsets in the removed text end up at the end of the new text.
+    assert!(moved(&language, "a:\n  b\n", 4, 3, 1).contains([
+        "    Newline (2, 5)",
+        "    Indent (5, 5)",
+        "    Statement (5, 5)",
+        "      Name (5, 5)",
+        "      Newline (5, 5)",
+        "    Dedent (5, 5)",
+    ].join("\n").as_str()));
+}
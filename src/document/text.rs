@@ -0,0 +1,784 @@
+use std::{fmt, mem, str};
+
+use crate::document::Span;
+
+/// The most bytes a leaf of the rope holds. Leaves are only split at character boundaries, so
+/// a leaf can be a few bytes short of this.
+const LEAF_SIZE: usize = 1024;
+
+/// The Source for a document.
+///
+/// The text is kept in a rope, a balanced tree of small strings, so an edit only rebuilds the
+/// path down to where it is, instead of moving the whole rest of the text. Every part of the
+/// tree keeps its length in bytes, chars, UTF-16 code units and lines, which makes an index
+/// of the lines that is kept up to date by the edits themselves. Offsets, lines and columns
+/// are found and converted in O(log n) too.
+pub struct Text {
+    /// The root of the rope.
+    root: Rope,
+}
+
+/// The size of a piece of text.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+struct Metrics {
+    /// The length in bytes.
+    bytes: usize,
+
+    /// The length in chars.
+    chars: usize,
+
+    /// The length in UTF-16 code units.
+    utf16: usize,
+
+    /// The number of newlines.
+    lines: usize,
+}
+
+impl Metrics {
+    /// Measure a string.
+    fn of(text: &str) -> Metrics {
+        return Metrics {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            utf16: text.chars().map(char::len_utf16).sum(),
+            lines: text.bytes().filter(|byte| *byte == b'\n').count(),
+        };
+    }
+
+    /// The size of two pieces of text put together.
+    fn add(self, other: Metrics) -> Metrics {
+        return Metrics {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            utf16: self.utf16 + other.utf16,
+            lines: self.lines + other.lines,
+        };
+    }
+}
+
+/// What columns and offsets are counted in. Editors tend to count in chars, while protocols
+/// like LSP count in UTF-16 code units.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Unit {
+    /// UTF-8 bytes, like the offsets in the document.
+    Utf8,
+
+    /// Chars, or unicode scalar values.
+    Char,
+
+    /// UTF-16 code units.
+    Utf16,
+}
+
+impl Unit {
+    /// The size of a piece of text in the unit.
+    fn of(self, metrics: Metrics) -> usize {
+        return match self {
+            Unit::Utf8 => metrics.bytes,
+            Unit::Char => metrics.chars,
+            Unit::Utf16 => metrics.utf16,
+        };
+    }
+
+    /// The size of a character in the unit.
+    fn width(self, chr: char) -> usize {
+        return match self {
+            Unit::Utf8 => chr.len_utf8(),
+            Unit::Char => 1,
+            Unit::Utf16 => chr.len_utf16(),
+        };
+    }
+}
+
+/// A place in the text as a line and a column, both counting from 0.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Debug)]
+pub struct LineColumn {
+    /// The line.
+    pub line: usize,
+
+    /// How far into the line it is, in some Unit.
+    pub column: usize,
+}
+
+/// A part of the rope. Branches are kept AVL balanced, and never have an empty side.
+enum Rope {
+    /// A piece of the text.
+    Leaf(String, Metrics),
+
+    /// Two parts of the rope, one after the other.
+    Branch {
+        left: Box<Rope>,
+        right: Box<Rope>,
+        metrics: Metrics,
+        height: usize,
+    },
+}
+
+impl Rope {
+    /// A single leaf.
+    fn leaf(text: String) -> Rope {
+        let metrics = Metrics::of(&text);
+        return Rope::Leaf(text, metrics);
+    }
+
+    /// A branch over the two parts, without balancing it.
+    fn branch(left: Rope, right: Rope) -> Rope {
+        return Rope::Branch {
+            metrics: left.metrics().add(right.metrics()),
+            height: left.height().max(right.height()) + 1,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+    }
+
+    /// Build a balanced rope from a string.
+    fn from_str(text: &str) -> Rope {
+        let mut leaves = vec![];
+        let mut rest = text;
+
+        while rest.len() > LEAF_SIZE {
+            let mut end = LEAF_SIZE;
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            leaves.push(Rope::leaf(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+
+        leaves.push(Rope::leaf(rest.to_string()));
+
+        return Rope::from_leaves(leaves);
+    }
+
+    /// Build a balanced rope from leaves, by halving them.
+    fn from_leaves(mut leaves: Vec<Rope>) -> Rope {
+        if leaves.len() == 1 {
+            return leaves.pop().unwrap();
+        }
+
+        let right = leaves.split_off(leaves.len() / 2);
+
+        return Rope::branch(Rope::from_leaves(leaves), Rope::from_leaves(right));
+    }
+
+    /// The size of the part.
+    fn metrics(&self) -> Metrics {
+        return match self {
+            Rope::Leaf(_, metrics) => *metrics,
+            Rope::Branch { metrics, .. } => *metrics,
+        };
+    }
+
+    /// The height of the part, with leaves being 0.
+    fn height(&self) -> usize {
+        return match self {
+            Rope::Leaf(..) => 0,
+            Rope::Branch { height, .. } => *height,
+        };
+    }
+
+    /// Take apart a branch.
+    fn children(self) -> (Rope, Rope) {
+        return match self {
+            Rope::Branch { left, right, .. } => (*left, *right),
+            Rope::Leaf(..) => unreachable!("a leaf has no children"),
+        };
+    }
+
+    /// Put two ropes one after the other, keeping it balanced. This takes time for the
+    /// difference in their heights.
+    fn join(left: Rope, right: Rope) -> Rope {
+        if left.metrics().bytes == 0 {
+            return right;
+        }
+
+        if right.metrics().bytes == 0 {
+            return left;
+        }
+
+        // Small leaves next to each other are merged, so edits don't leave lots of tiny leaves.
+        if let (Rope::Leaf(first, _), Rope::Leaf(second, _)) = (&left, &right) {
+            if first.len() + second.len() <= LEAF_SIZE {
+                return Rope::leaf(first.clone() + second);
+            }
+        }
+
+        if left.height() > right.height() + 1 {
+            let (outer, inner) = left.children();
+            return Rope::balance(outer, Rope::join(inner, right));
+        }
+
+        if right.height() > left.height() + 1 {
+            let (inner, outer) = right.children();
+            return Rope::balance(Rope::join(left, inner), outer);
+        }
+
+        return Rope::branch(left, right);
+    }
+
+    /// A branch over the two parts, rotating it if one side is two taller than the other.
+    fn balance(left: Rope, right: Rope) -> Rope {
+        if left.height() > right.height() + 1 {
+            let (outer, inner) = left.children();
+
+            if outer.height() >= inner.height() {
+                return Rope::branch(outer, Rope::branch(inner, right));
+            }
+
+            let (first, second) = inner.children();
+            return Rope::branch(Rope::branch(outer, first), Rope::branch(second, right));
+        }
+
+        if right.height() > left.height() + 1 {
+            let (inner, outer) = right.children();
+
+            if outer.height() >= inner.height() {
+                return Rope::branch(Rope::branch(left, inner), outer);
+            }
+
+            let (first, second) = inner.children();
+            return Rope::branch(Rope::branch(left, first), Rope::branch(second, outer));
+        }
+
+        return Rope::branch(left, right);
+    }
+
+    /// Cut the rope in two at a byte offset.
+    fn split(self, offset: usize) -> (Rope, Rope) {
+        return match self {
+            Rope::Leaf(mut text, _) => {
+                let right = text.split_off(offset);
+                (Rope::leaf(text), Rope::leaf(right))
+            },
+            Rope::Branch { left, right, .. } => {
+                let size = left.metrics().bytes;
+
+                if offset <= size {
+                    let (first, second) = left.split(offset);
+                    (first, Rope::join(second, *right))
+                } else {
+                    let (first, second) = right.split(offset - size);
+                    (Rope::join(*left, first), second)
+                }
+            },
+        };
+    }
+
+    /// Find the leaf for a position, counting with one of the metrics. Returns the leaf, and
+    /// the size of everything before it. A position on the border of two leaves goes to the
+    /// first one, so the end of the text can be found.
+    fn find(&self, position: usize, measure: impl Fn(Metrics) -> usize) -> (&str, Metrics) {
+        let mut rope = self;
+        let mut before = Metrics::default();
+
+        loop {
+            match rope {
+                Rope::Leaf(text, _) => return (text, before),
+                Rope::Branch { left, right, .. } => {
+                    let size = left.metrics();
+
+                    if position <= measure(before.add(size)) {
+                        rope = left;
+                    } else {
+                        before = before.add(size);
+                        rope = right;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Add the text in the span to a string. The span is relative to this part.
+    fn slice_into(&self, span: Span, out: &mut String) {
+        match self {
+            Rope::Leaf(text, _) => out.push_str(&text[span.0..span.1]),
+            Rope::Branch { left, right, .. } => {
+                let size = left.metrics().bytes;
+
+                if span.0 < size {
+                    left.slice_into((span.0, span.1.min(size)), out);
+                }
+
+                if span.1 > size {
+                    right.slice_into((span.0.max(size) - size, span.1 - size), out);
+                }
+            },
+        }
+    }
+}
+
+impl Text {
+    /// Initializes an empty text.
+    pub fn new() -> Text {
+        return Text { root: Rope::leaf(String::new()) };
+    }
+
+    /// Replace the given span of text with the edit.
+    pub fn edit(&mut self, span: Span, edit: &str) {
+        let root = mem::replace(&mut self.root, Rope::leaf(String::new()));
+
+        let (before, rest) = root.split(span.0);
+        let (_, after) = rest.split(span.1 - span.0);
+
+        self.root = Rope::join(Rope::join(before, Rope::from_str(edit)), after);
+    }
+
+    /// Read the character at a given byte offset.
+    pub fn read(&self, offset: usize) -> Option<char> {
+        // The leaf before a border is found, so step over to the next one for its first char.
+        let (leaf, before) = self.root.find(offset + 1, |metrics| metrics.bytes);
+
+        return leaf[offset - before.bytes..].chars().next();
+    }
+
+    /// Read the byte at a given byte offset, which doesn't have to be the start of a character.
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        let (leaf, before) = self.root.find(offset + 1, |metrics| metrics.bytes);
+
+        return leaf.as_bytes().get(offset - before.bytes).copied();
+    }
+
+    /// Copy out the text in the span.
+    pub fn slice(&self, span: Span) -> String {
+        let mut out = String::with_capacity(span.1 - span.0);
+
+        if span.0 < span.1 {
+            self.root.slice_into(span, &mut out);
+        }
+
+        return out;
+    }
+
+    /// Cheacks if the character at the given byte offset is a newline.
+    pub fn is_newline(&self, offset: usize) -> bool {
+        self.read(offset).map_or(true, |chr| chr == '\n')
+    }
+
+    /// Get the byte length of the text.
+    pub fn byte_len(&self) -> usize {
+        self.root.metrics().bytes
+    }
+
+    /// Get the length of the text in a unit.
+    pub fn len(&self, unit: Unit) -> usize {
+        unit.of(self.root.metrics())
+    }
+
+    /// Get the number of lines in the text. An empty text, or a text ending with a newline,
+    /// still has a last, empty line.
+    pub fn line_count(&self) -> usize {
+        self.root.metrics().lines + 1
+    }
+
+    /// Each character in the text, with its byte offset.
+    pub fn chars_indices(&self) -> CharIndices<'_> {
+        return CharIndices {
+            leaves: Leaves(vec![&self.root]),
+            chars: "".char_indices(),
+            base: 0,
+            next: 0,
+        };
+    }
+
+    /// Count the units in the text before a byte offset.
+    pub fn byte_to_unit(&self, offset: usize, unit: Unit) -> usize {
+        let (leaf, before) = self.root.find(offset, |metrics| metrics.bytes);
+
+        return unit.of(before) + unit.of(Metrics::of(&leaf[..offset - before.bytes]));
+    }
+
+    /// Find the byte offset after a count of units. A count that ends inside of a character,
+    /// like half of a UTF-16 surrogate pair, goes back to the start of that character. Counts
+    /// past the end go to the end of the text.
+    pub fn unit_to_byte(&self, count: usize, unit: Unit) -> usize {
+        let (leaf, before) = self.root.find(count, |metrics| unit.of(metrics));
+        let mut counted = unit.of(before);
+
+        for (index, chr) in leaf.char_indices() {
+            if counted + unit.width(chr) > count {
+                return before.bytes + index;
+            }
+
+            counted += unit.width(chr);
+        }
+
+        return before.bytes + leaf.len();
+    }
+
+    /// Find the line a byte offset is on, counting from 0. A newline is on the line it ends.
+    pub fn byte_to_line(&self, offset: usize) -> usize {
+        let (leaf, before) = self.root.find(offset, |metrics| metrics.bytes);
+
+        return before.lines + Metrics::of(&leaf[..offset - before.bytes]).lines;
+    }
+
+    /// Find the byte offset a line starts at. Lines past the end start at the end of the text.
+    pub fn line_start(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+
+        if line >= self.line_count() {
+            return self.byte_len();
+        }
+
+        // Look for the newline before the line.
+        let (leaf, before) = self.root.find(line, |metrics| metrics.lines);
+
+        let newline = leaf.match_indices('\n')
+            .nth(line - before.lines - 1)
+            .map_or(leaf.len(), |(index, _)| index);
+
+        return before.bytes + newline + 1;
+    }
+
+    /// Find the byte offset a line ends at, before its line break. A `\r\n` line break is left
+    /// out as a whole. Lines past the end end at the end of the text.
+    pub fn line_end(&self, line: usize) -> usize {
+        if line + 1 >= self.line_count() {
+            return self.byte_len();
+        }
+
+        let end = self.line_start(line + 1) - 1;
+
+        // The byte before the newline may be the end of a longer character, so it can't be read
+        // as a char.
+        if end > self.line_start(line) && self.byte(end - 1) == Some(b'\r') {
+            return end - 1;
+        }
+
+        return end;
+    }
+
+    /// Convert a byte offset into a line and a column counted in the unit.
+    pub fn line_column(&self, offset: usize, unit: Unit) -> LineColumn {
+        let line = self.byte_to_line(offset);
+        let start = self.line_start(line);
+
+        return LineColumn {
+            line,
+            column: self.byte_to_unit(offset, unit) - self.byte_to_unit(start, unit),
+        };
+    }
+
+    /// Convert a line and a column counted in the unit into a byte offset. Columns past the
+    /// end of the line go to the end of the line, so a cursor moving between lines stays on
+    /// the line it moved to.
+    pub fn offset(&self, position: LineColumn, unit: Unit) -> usize {
+        let start = self.line_start(position.line);
+        let end = self.line_end(position.line);
+
+        let offset = self.unit_to_byte(self.byte_to_unit(start, unit) + position.column, unit);
+
+        return offset.min(end);
+    }
+}
+
+impl Default for Text {
+    fn default() -> Text {
+        return Text::new();
+    }
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Text {
+        return Text { root: Rope::from_str(text) };
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for leaf in Leaves(vec![&self.root]) {
+            f.write_str(leaf)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return fmt::Debug::fmt(&self.to_string(), f);
+    }
+}
+
+/// The leaves of a rope in order, from a stack of the parts still to visit.
+struct Leaves<'a>(Vec<&'a Rope>);
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut rope = self.0.pop()?;
+
+        loop {
+            match rope {
+                Rope::Leaf(text, _) => return Some(text),
+                Rope::Branch { left, right, .. } => {
+                    self.0.push(right);
+                    rope = left;
+                },
+            }
+        }
+    }
+}
+
+/// An iterator over the characters of a Text, with their byte offsets.
+pub struct CharIndices<'a> {
+    /// The leaves still to visit.
+    leaves: Leaves<'a>,
+
+    /// The characters in the current leaf.
+    chars: str::CharIndices<'a>,
+
+    /// The byte offset of the current leaf.
+    base: usize,
+
+    /// The byte offset of the next leaf.
+    next: usize,
+}
+
+impl<'a> Iterator for CharIndices<'a> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        loop {
+            if let Some((index, chr)) = self.chars.next() {
+                return Some((self.base + index, chr));
+            }
+
+            let leaf = self.leaves.next()?;
+            self.base = self.next;
+            self.next += leaf.len();
+            self.chars = leaf.char_indices();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small random number generator, so the test is the same every run.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, max: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            return (self.0 % max as u64) as usize;
+        }
+    }
+
+    /// The offset of every character in the string and its end, with the units before each.
+    fn boundaries(text: &str, unit: Unit) -> Vec<(usize, usize)> {
+        let mut counted = 0;
+        let mut out = vec![];
+
+        for (index, chr) in text.char_indices() {
+            out.push((index, counted));
+            counted += unit.width(chr);
+        }
+
+        out.push((text.len(), counted));
+        return out;
+    }
+
+    /// Check every conversion of the text against the same thing worked out on the string.
+    fn check(text: &Text, model: &str) {
+        assert_eq!(text.to_string(), model);
+
+        let mut starts = vec![0];
+        starts.extend(model.match_indices('\n').map(|(index, _)| index + 1));
+        assert_eq!(text.line_count(), starts.len());
+
+        for (line, start) in starts.iter().enumerate() {
+            let end = match starts.get(line + 1) {
+                Some(next) if next - 1 > *start && model.as_bytes()[next - 2] == b'\r' => next - 2,
+                Some(next) => next - 1,
+                None => model.len(),
+            };
+
+            assert_eq!(text.line_start(line), *start);
+            assert_eq!(text.line_end(line), end);
+        }
+
+        assert_eq!(text.line_start(starts.len()), model.len());
+        assert_eq!(text.line_end(starts.len()), model.len());
+
+        for unit in [Unit::Utf8, Unit::Char, Unit::Utf16] {
+            let boundaries = boundaries(model, unit);
+            assert_eq!(text.len(unit), boundaries.last().unwrap().1);
+
+            for (offset, count) in &boundaries {
+                assert_eq!(text.byte_to_unit(*offset, unit), *count);
+                assert_eq!(text.unit_to_byte(*count, unit), *offset);
+
+                let line = starts.partition_point(|start| start <= offset) - 1;
+                let start = boundaries.binary_search_by_key(&starts[line], |(offset, _)| *offset).unwrap();
+                let column = count - boundaries[start].1;
+
+                assert_eq!(text.line_column(*offset, unit), LineColumn { line, column });
+                assert_eq!(text.offset(LineColumn { line, column }, unit), *offset.min(&text.line_end(line)));
+            }
+
+            // Counts inside of a character go back to its start, and counts past the end go to
+            // the end.
+            for pair in boundaries.windows(2) {
+                for count in pair[0].1..pair[1].1 {
+                    assert_eq!(text.unit_to_byte(count, unit), pair[0].0);
+                }
+            }
+
+            assert_eq!(text.unit_to_byte(boundaries.last().unwrap().1 + 1, unit), model.len());
+
+            // Columns past the end of a line stay on the line.
+            for line in 0..starts.len() {
+                let far = LineColumn { line, column: usize::MAX / 2 };
+                assert_eq!(text.offset(far, unit), text.line_end(line));
+            }
+        }
+    }
+
+    /// Check that every part of the rope is balanced, has its sizes right, and has no empty
+    /// side or leaf too big. Returns the text of the part.
+    fn structure(rope: &Rope) -> String {
+        let text = match rope {
+            Rope::Leaf(text, _) => {
+                assert!(text.len() <= LEAF_SIZE);
+                text.clone()
+            },
+            Rope::Branch { left, right, height, .. } => {
+                assert!(left.metrics().bytes > 0 && right.metrics().bytes > 0);
+                assert!(left.height().abs_diff(right.height()) <= 1);
+                assert_eq!(*height, left.height().max(right.height()) + 1);
+
+                structure(left) + &structure(right)
+            },
+        };
+
+        assert_eq!(rope.metrics(), Metrics::of(&text));
+        return text;
+    }
+
+    /// Check reading the text at every offset against the same thing on the string.
+    fn check_reads(text: &Text, model: &str) {
+        assert_eq!(structure(&text.root), model);
+        assert_eq!(text.byte_len(), model.len());
+        assert!(text.chars_indices().eq(model.char_indices()));
+
+        for offset in 0..=model.len() {
+            assert_eq!(text.byte(offset), model.as_bytes().get(offset).copied());
+
+            if model.is_char_boundary(offset) {
+                assert_eq!(text.read(offset), model[offset..].chars().next());
+                assert_eq!(text.is_newline(offset), model[offset..].chars().next().map_or(true, |chr| chr == '\n'));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_across_leaves() {
+        let model = "ab\né中文😀\n".repeat(300);
+        let text = Text::from(model.as_str());
+
+        // Enough text for the rope to have a few levels.
+        assert!(text.root.height() >= 2);
+        check_reads(&text, &model);
+
+        let chars: Vec<usize> = model.char_indices().map(|(index, _)| index)
+            .chain(std::iter::once(model.len()))
+            .collect();
+
+        for (index, start) in chars.iter().enumerate().step_by(97) {
+            for end in chars[index..].iter().step_by(211) {
+                assert_eq!(text.slice((*start, *end)), model[*start..*end]);
+            }
+        }
+
+        assert_eq!(text.slice((5, 5)), "");
+    }
+
+    #[test]
+    fn stays_balanced_after_edits() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let mut text = Text::new();
+        let mut model = String::new();
+
+        check_reads(&text, &model);
+
+        for round in 0..200 {
+            let chars: Vec<usize> = model.char_indices().map(|(index, _)| index)
+                .chain(std::iter::once(model.len()))
+                .collect();
+
+            let start = chars[random.below(chars.len())];
+            let end = chars[random.below(chars.len())].max(start);
+
+            // Grow the text most of the time, with a big edit every so often to make new leaves.
+            let edit = "xé😀\n".repeat(random.below(if round % 20 == 0 { 2000 } else { 30 }));
+
+            text.edit((start, end), &edit);
+            model.replace_range(start..end, &edit);
+
+            structure(&text.root);
+        }
+
+        check_reads(&text, &model);
+
+        // Taking out all of the text leaves nothing behind.
+        text.edit((0, model.len()), "");
+        check_reads(&text, "");
+    }
+
+    #[test]
+    fn matches_a_string_after_random_edits() {
+        let pieces = ["a", "xyz", "\n", "\r\n", "\r", "é", "中文", "😀", "中\n", "😀\r\n", "\n\n"];
+
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let mut text = Text::new();
+        let mut model = String::new();
+
+        for round in 0..300 {
+            let chars: Vec<usize> = model.char_indices().map(|(index, _)| index)
+                .chain(std::iter::once(model.len()))
+                .collect();
+
+            let start = chars[random.below(chars.len())];
+            let end = if random.below(3) == 0 {
+                start
+            } else {
+                chars[random.below(chars.len())].max(start).min(start + 64)
+            };
+            let end = *chars.iter().find(|offset| **offset >= end).unwrap();
+
+            // Grow the text most of the time, so it spans several leaves.
+            let mut edit = String::new();
+            for _ in 0..random.below(if round % 10 == 0 { 300 } else { 12 }) {
+                edit.push_str(pieces[random.below(pieces.len())]);
+            }
+
+            text.edit((start, end), &edit);
+            model.replace_range(start..end, &edit);
+
+            if round % 50 == 0 || model.len() < 200 {
+                check(&text, &model);
+            }
+        }
+
+        check(&text, &model);
+    }
+
+    #[test]
+    fn line_end_after_a_multibyte_character() {
+        let text = Text::from("中文\nx\r\n😀\r\n");
+
+        assert_eq!(text.line_end(0), "中文".len());
+        assert_eq!(text.line_end(1), "中文\nx".len());
+        assert_eq!(text.line_end(2), "中文\nx\r\n😀".len());
+        assert_eq!(text.offset(LineColumn { line: 0, column: 5 }, Unit::Char), "中文".len());
+    }
+}
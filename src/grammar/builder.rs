@@ -0,0 +1,179 @@
+use std::panic::Location;
+
+use crate::rules::{CharClass, Language, ListOptions};
+
+use super::{Action, Definition, Expr, Grammar, GrammarError, Position, PrattOperator};
+
+/// Builds a grammar in Rust code, as an alternative to writing out its source. It compiles
+/// the same way, so the steps are numbered automatically.
+///
+/// ```text
+/// let mut grammar = GrammarBuilder::new();
+///
+/// grammar.token("Name", many1(range('a', 'z')));
+/// grammar.token("Number", many1(range('0', '9')));
+///
+/// grammar.rule("File", many(name("Assign")));
+/// grammar.rule("Assign", seq(vec![name("Name"), token("="), name("Number")]));
+///
+/// let language = grammar.build()?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GrammarBuilder {
+    /// The grammar built so far.
+    grammar: Grammar,
+
+    /// The lexer mode tokens are defined in, or `None` for the default one.
+    mode: Option<String>,
+}
+
+impl GrammarBuilder {
+    /// Initializes a builder with no definitions.
+    pub fn new() -> GrammarBuilder {
+        return GrammarBuilder::default();
+    }
+
+    /// Define a token. The position of the definition is where this was called from.
+    #[track_caller]
+    pub fn token(&mut self, name: &str, expr: Expr) -> &mut GrammarBuilder {
+        let mut token = definition(name, expr, Location::caller());
+        token.mode = self.mode.clone();
+
+        self.grammar.tokens.push(token);
+        return self;
+    }
+
+    /// Define the tokens after this in the lexer mode, rather than the default one.
+    pub fn mode(&mut self, mode: &str) -> &mut GrammarBuilder {
+        self.mode = Some(mode.to_string());
+        return self;
+    }
+
+    /// Make the last token defined enter the mode.
+    pub fn push(&mut self, mode: &str) -> &mut GrammarBuilder {
+        if let Some(token) = self.grammar.tokens.last_mut() {
+            token.action = Some(Action::Push(mode.to_string()));
+        }
+
+        return self;
+    }
+
+    /// Make the last token defined leave the mode it's in.
+    pub fn pop(&mut self) -> &mut GrammarBuilder {
+        if let Some(token) = self.grammar.tokens.last_mut() {
+            token.action = Some(Action::Pop);
+        }
+
+        return self;
+    }
+
+    /// Define a rule. The first rule is the file rule. The position of the definition is
+    /// where this was called from.
+    #[track_caller]
+    pub fn rule(&mut self, name: &str, expr: Expr) -> &mut GrammarBuilder {
+        self.grammar.rules.push(definition(name, expr, Location::caller()));
+        return self;
+    }
+
+    /// The grammar built so far.
+    pub fn grammar(&self) -> &Grammar {
+        return &self.grammar;
+    }
+
+    /// Compile the grammar into a language, or find every error in it.
+    pub fn build(&self) -> Result<Language, Vec<GrammarError>> {
+        return self.grammar.compile();
+    }
+}
+
+/// Make a definition at a place in the Rust source.
+fn definition(name: &str, expr: Expr, location: &Location) -> Definition {
+    return Definition {
+        name: name.to_string(),
+        expr,
+        position: Position {
+            line: location.line() as usize,
+            column: location.column() as usize,
+        },
+        mode: None,
+        action: None,
+    };
+}
+
+/// A reference to a token or rule.
+pub fn name(name: &str) -> Expr {
+    return Expr::Name(name.to_string());
+}
+
+/// A literal piece of text. In a rule, it matches a token with exactly that text.
+pub fn token(text: &str) -> Expr {
+    return Expr::Text(text.to_string());
+}
+
+/// An inclusive range of characters, for use in tokens.
+pub fn range(start: char, end: char) -> Expr {
+    return Expr::Range(start, end);
+}
+
+/// Each expression in order.
+pub fn seq(exprs: Vec<Expr>) -> Expr {
+    return Expr::Seq(exprs);
+}
+
+/// Any one of the expressions.
+pub fn choice(exprs: Vec<Expr>) -> Expr {
+    return Expr::Choice(exprs);
+}
+
+/// The expression, or nothing.
+pub fn optional(expr: Expr) -> Expr {
+    return Expr::Optional(Box::new(expr));
+}
+
+/// The expression zero or more times.
+pub fn many(expr: Expr) -> Expr {
+    return Expr::Many(Box::new(expr));
+}
+
+/// The expression one or more times.
+pub fn many1(expr: Expr) -> Expr {
+    return Expr::Many1(Box::new(expr));
+}
+
+/// Zero or more of the item, with the separator between each of them.
+pub fn sep_by(item: Expr, separator: Expr) -> Expr {
+    return optional(seq(vec![item.clone(), many(seq(vec![separator, item]))]));
+}
+
+/// Any one character in the class, for use in tokens.
+pub fn class(class: CharClass) -> Expr {
+    return Expr::Class(class);
+}
+
+/// In a token, any one character not in the class of the expression. In a rule, a predicate
+/// that matches if the expression doesn't.
+pub fn not(expr: Expr) -> Expr {
+    return Expr::Not(Box::new(expr));
+}
+
+/// A predicate that matches if the expression does, without consuming anything.
+pub fn and(expr: Expr) -> Expr {
+    return Expr::And(Box::new(expr));
+}
+
+/// Any one of the expressions, tried in order.
+pub fn ordered(exprs: Vec<Expr>) -> Expr {
+    return Expr::Ordered(exprs);
+}
+
+/// A list of the named element, with the named separator between them. It has to be the
+/// whole of a rule.
+pub fn list(element: &str, separator: &str, options: ListOptions) -> Expr {
+    return Expr::List(element.to_string(), separator.to_string(), options);
+}
+
+/// Operators applied to the named operands, which are tried in order. It has to be the whole
+/// of a rule.
+pub fn pratt(operands: Vec<&str>, operators: Vec<PrattOperator>) -> Expr {
+    return Expr::Pratt(operands.into_iter().map(|operand| operand.to_string()).collect(), operators);
+}
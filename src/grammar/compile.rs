@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+
+use crate::document::Kind;
+use crate::rules::{
+    And, Automata, CharClass, Edge, Language, LanguageBuilder, Lexer, List, Mode, ModeAction, Not, Operator,
+    Pratt, Rule, Step,
+};
+
+use super::nfa::Nfa;
+use super::tokens;
+use super::{Action, Definition, Expr, Grammar, GrammarError, LEXER};
+
+/// Compile a grammar into a language. The rules are registered under their own names, and
+/// the lexer holding all of the tokens under `LEXER`. Every error found is returned, each at
+/// the definition it is in.
+///
+/// Some rules need helper rules, which are named after them like `Rule:1`.
+pub fn compile(grammar: &Grammar) -> Result<Language, Vec<GrammarError>> {
+    if grammar.rules.is_empty() {
+        return Err(vec![error(
+            grammar.tokens.first(),
+            "a grammar needs at least one rule, the first one is the file rule".to_string(),
+        )]);
+    }
+
+    let mut errors = vec![];
+
+    let defs: Vec<&Definition> = grammar.tokens.iter().chain(&grammar.rules).collect();
+    for (i, def) in defs.iter().enumerate() {
+        if defs[..i].iter().any(|other| other.name == def.name) {
+            errors.push(error(Some(def), format!("`{}` is defined more than once", def.name)));
+        }
+    }
+
+    let literals = literals(grammar);
+    let has_lexer = !grammar.tokens.is_empty() || !literals.is_empty();
+
+    if let Some(def) = grammar.rules.iter().find(|def| def.name == LEXER && has_lexer) {
+        errors.push(error(Some(def), format!("`{}` is the name of the rule holding the tokens", LEXER)));
+    }
+
+    let mut language = LanguageBuilder::new();
+
+    // The tokens come first, so the literals in the rules know which token they are.
+    let (lexer, kinds) = lexer(grammar, &literals, &mut language, &mut errors);
+
+    for def in &grammar.rules {
+        if let Err(rule_error) = compile_rule(grammar, def, &kinds, &mut language) {
+            errors.push(rule_error);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if has_lexer {
+        language.rule(LEXER, lexer);
+    }
+
+    return language.build().map_err(|errors| errors.iter().map(|language_error| {
+        return error(definition(grammar, language_error.rule()), language_error.to_string());
+    }).collect());
+}
+
+/// Build an error at the given definition.
+fn error(def: Option<&Definition>, message: String) -> GrammarError {
+    return GrammarError {
+        position: def.map_or(super::Position { line: 1, column: 1 }, |def| def.position),
+        message,
+    };
+}
+
+/// Find the definition of a compiled rule. The lexer is defined by the tokens, or by the
+/// literals in the rules if there are none, and helper rules by the rule they are for.
+fn definition<'a>(grammar: &'a Grammar, name: Option<&str>) -> Option<&'a Definition> {
+    let name = name?.split(':').next()?;
+
+    return grammar.rules.iter()
+        .chain(&grammar.tokens)
+        .find(|def| def.name == name)
+        .or_else(|| if name == LEXER { grammar.tokens.first().or(grammar.rules.first()) } else { None });
+}
+
+/// Compile a rule, and register it along with any helper rules it needs.
+fn compile_rule(
+    grammar: &Grammar,
+    def: &Definition,
+    kinds: &HashMap<String, Kind>,
+    language: &mut LanguageBuilder,
+) -> Result<(), GrammarError> {
+    let kind = rule_kind(grammar, &def.name, language);
+
+    match &def.expr {
+        Expr::List(element, separator, options) => {
+            let element = reference(grammar, def, element, language)?;
+            let separator = reference(grammar, def, separator, language)?;
+
+            language.rule(&def.name, List::new((&element.0, element.1), (&separator.0, separator.1), kind, *options));
+        },
+        Expr::Pratt(operands, operators) => {
+            let operands = operands.iter()
+                .map(|operand| reference(grammar, def, operand, language))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut table = vec![];
+            for operator in operators {
+                if !grammar.tokens.iter().any(|token| token.name == operator.token) {
+                    return Err(error(Some(def), format!("the operator `{}` has to be a token", operator.token)));
+                }
+
+                table.push(Operator {
+                    token: language.kind(&operator.token),
+                    precedence: operator.precedence,
+                    fixity: operator.fixity,
+                    kind: language.kind(&operator.kind),
+                });
+            }
+
+            // The result is one of the operands or operations, so it's put in a node of the
+            // rule's own kind, like the result of any other rule.
+            let mut results = vec![];
+            for result in operands.iter().map(|(_, kind)| *kind).chain(table.iter().map(|operator| operator.kind)) {
+                if !results.contains(&result) {
+                    results.push(result);
+                }
+            }
+
+            let pratt = format!("{}:pratt", def.name);
+            let operands = operands.iter().map(|(rule, kind)| (rule.as_str(), *kind)).collect();
+            language.rule(&pratt, Pratt::new(operands, LEXER, table));
+
+            language.rule(&def.name, Automata::new(vec![
+                Step(results.into_iter().map(|result| ((pratt.as_str(), result), 1)).collect(), None),
+                Step(vec![], Some(kind)),
+            ]));
+        },
+        _ => {
+            let steps = rule_steps(grammar, def, kinds, language)?;
+            let automata = if is_ordered(&def.expr) { Automata::ordered(steps) } else { Automata::new(steps) };
+
+            language.rule(&def.name, automata);
+        },
+    }
+
+    return Ok(());
+}
+
+/// The rule and kind of node a name refers to.
+fn reference(
+    grammar: &Grammar,
+    def: &Definition,
+    name: &str,
+    language: &mut LanguageBuilder,
+) -> Result<(String, Kind), GrammarError> {
+    if grammar.rules.iter().any(|rule| rule.name == name) {
+        return Ok((name.to_string(), rule_kind(grammar, name, language)));
+    }
+
+    if grammar.tokens.iter().any(|token| token.name == name) {
+        return Ok((LEXER.to_string(), language.kind(name)));
+    }
+
+    return Err(error(Some(def), format!("`{}` is not defined", name)));
+}
+
+/// Build the automata steps for a rule. Literals become edges to the token they are, with
+/// their kinds given by `kinds`, and predicates edges to helper rules.
+fn rule_steps(
+    grammar: &Grammar,
+    def: &Definition,
+    kinds: &HashMap<String, Kind>,
+    language: &mut LanguageBuilder,
+) -> Result<Vec<Step<Edge>>, GrammarError> {
+    // Each distinct edge gets its own symbol.
+    let mut edges: Vec<Edge> = vec![];
+    let mut symbol = |edge: Edge| {
+        if let Some(symbol) = edges.iter().position(|other| *other == edge) {
+            return symbol;
+        }
+
+        edges.push(edge);
+        return edges.len() - 1;
+    };
+
+    // The literals of the rule that are each kind of token.
+    let mut literals = vec![];
+    find_literals(&def.expr, &mut literals);
+
+    let mut helpers = 0;
+    let mut helper = || {
+        helpers += 1;
+        return format!("{}:{}", def.name, helpers);
+    };
+
+    let mut nfa = Nfa::new();
+    nfa.add(&def.expr, 0, &mut |expr: &Expr| {
+        return match expr {
+            Expr::Name(name) if grammar.tokens.iter().any(|token| &token.name == name) => {
+                let kind = language.kind(name);
+                let texts: Vec<&str> = literals.iter()
+                    .filter(|literal| kinds[*literal] == kind)
+                    .map(|literal| literal.as_str())
+                    .collect();
+
+                // A token and a literal of the same kind can be the same node, so the token
+                // is split into the literals and everything else. Otherwise the automata
+                // couldn't tell which of the two edges to take.
+                let mut symbols = vec![symbol(Edge::except(LEXER, kind, &texts))];
+                symbols.extend(texts.iter().map(|text| symbol(Edge::text(LEXER, kind, text))));
+
+                Ok(symbols)
+            },
+            Expr::Name(name) => {
+                let (rule, kind) = reference(grammar, def, name, language)?;
+                Ok(vec![symbol(Edge::new(&rule, kind))])
+            },
+            Expr::Text(text) if text.is_empty() => Err(error(Some(def), "empty literal".to_string())),
+            Expr::Text(text) => Ok(vec![symbol(Edge::text(LEXER, kinds[text], text))]),
+            Expr::And(target) | Expr::Not(target) => {
+                // A predicate can only look for a single rule, so anything more is a rule of its own.
+                let (rule, kind) = match &**target {
+                    Expr::Name(name) => reference(grammar, def, name, language)?,
+                    _ => {
+                        let target = Definition {
+                            name: helper(),
+                            expr: (**target).clone(),
+                            position: def.position,
+                            mode: None,
+                            action: None,
+                        };
+
+                        compile_rule(grammar, &target, kinds, language)?;
+                        let kind = language.kind(&target.name);
+
+                        (target.name, kind)
+                    },
+                };
+
+                let predicate: Box<dyn Rule> = match expr {
+                    Expr::And(_) => And::new((&rule, kind)),
+                    _ => Not::new((&rule, kind)),
+                };
+
+                let name = helper();
+                language.rule(&name, predicate);
+
+                Ok(vec![symbol(Edge::new(&name, kind))])
+            },
+            Expr::List(..) | Expr::Pratt(..) => Err(error(
+                Some(def),
+                "`list` and `pratt` have to be the whole of a rule".to_string(),
+            )),
+            _ => Err(error(
+                Some(def),
+                "character ranges and classes can only be used in a token".to_string(),
+            )),
+        };
+    })?;
+
+    let kind = rule_kind(grammar, &def.name, language);
+
+    return Ok(nfa.determinize().states.into_iter().map(|state| Step(
+        join_split_edges(state.edges.into_iter().map(|(symbol, next)| (edges[symbol].clone(), next)).collect()),
+        if !state.accept.is_empty() { Some(kind) } else { None },
+    )).collect());
+}
+
+/// Does the expression have options that are tried in order? Predicates are rules of their
+/// own, so the options in them don't count.
+fn is_ordered(expr: &Expr) -> bool {
+    return match expr {
+        Expr::Ordered(_) => true,
+        Expr::Seq(exprs) | Expr::Choice(exprs) => exprs.iter().any(is_ordered),
+        Expr::Optional(expr) | Expr::Many(expr) | Expr::Many1(expr) => is_ordered(expr),
+        _ => false,
+    };
+}
+
+/// Put a token that was split around its literals back together, where every part of it goes
+/// to the same step.
+fn join_split_edges(edges: Vec<(Edge, usize)>) -> Vec<(Edge, usize)> {
+    let whole: Vec<bool> = edges.iter().map(|(edge, next)| {
+        !edge.except.is_empty() && edge.except.iter().all(|text| {
+            edges.contains(&(Edge::text(LEXER, edge.kind, text), *next))
+        })
+    }).collect();
+
+    // The literal edges that are part of a whole token again.
+    let mut parts = vec![];
+    for ((edge, next), _) in edges.iter().zip(&whole).filter(|(_, whole)| **whole) {
+        parts.extend(edge.except.iter().map(|text| (Edge::text(LEXER, edge.kind, text), *next)));
+    }
+
+    return edges.into_iter()
+        .zip(whole)
+        .filter(|(edge, _)| !parts.contains(edge))
+        .map(|((edge, next), whole)| if whole { (Edge::new(LEXER, edge.kind), next) } else { (edge, next) })
+        .collect();
+}
+
+/// The kind of node a rule creates. The first rule is the file rule, so it creates the file.
+fn rule_kind(grammar: &Grammar, name: &str, language: &mut LanguageBuilder) -> Kind {
+    if grammar.rules[0].name == name {
+        return Kind::FILE;
+    }
+
+    return language.kind(name);
+}
+
+/// Build the lexer for all of the tokens, and find the kind of token each literal is. Tokens
+/// with errors are added to `errors` and left out.
+///
+/// A literal is whatever token the lexer makes of it, trying the default mode first. A literal
+/// that no token matches gets a token of its own in the default mode, declared after the
+/// others so it only wins when it matches more.
+fn lexer(
+    grammar: &Grammar,
+    literals: &[String],
+    language: &mut LanguageBuilder,
+    errors: &mut Vec<GrammarError>,
+) -> (Box<dyn Rule>, HashMap<String, Kind>) {
+    // The default mode comes first, then the others in the order they are used.
+    let mut modes: Vec<Option<&str>> = vec![None];
+    for token in &grammar.tokens {
+        if !modes.contains(&token.mode.as_deref()) {
+            modes.push(token.mode.as_deref());
+        }
+    }
+
+    let mut tokens = vec![vec![]; modes.len()];
+    let mut actions = vec![vec![]; modes.len()];
+
+    for token in &grammar.tokens {
+        let mode = modes.iter().position(|mode| *mode == token.mode.as_deref()).unwrap();
+        let kind = language.kind(&token.name);
+
+        let expr = match token_expr(token, &token.expr) {
+            Ok(expr) => expr,
+            Err(token_error) => {
+                errors.push(token_error);
+                continue;
+            },
+        };
+
+        match &token.action {
+            Some(Action::Push(target)) => match modes.iter().position(|mode| *mode == Some(target.as_str())) {
+                Some(target) => actions[mode].push((kind, ModeAction::Push(target))),
+                None => errors.push(error(Some(token), format!("there are no tokens in the mode `{}`", target))),
+            },
+            Some(Action::Pop) => actions[mode].push((kind, ModeAction::Pop)),
+            None => {},
+        }
+
+        tokens[mode].push((expr, kind));
+    }
+
+    // Conflicts are fine, as earlier tokens take priority over later ones.
+    let mut steps: Vec<_> = tokens.iter().map(|tokens| tokens::compile(tokens).steps).collect();
+
+    let mut kinds = HashMap::new();
+    let mut added = false;
+
+    for literal in literals {
+        let kind = match steps.iter().find_map(|steps| tokens::lex(steps, literal)) {
+            Some(kind) => kind,
+            None => {
+                let kind = language.kind(&format!("{:?}", literal));
+                tokens[0].push((Expr::Text(literal.clone()), kind));
+                added = true;
+
+                kind
+            },
+        };
+
+        kinds.insert(literal.clone(), kind);
+    }
+
+    if added {
+        steps[0] = tokens::compile(&tokens[0]).steps;
+    }
+
+    if modes.len() == 1 {
+        return (Lexer::longest(steps.remove(0)), kinds);
+    }
+
+    // The steps of each mode go after the ones before it, so their edges are moved along.
+    let mut all = vec![];
+    let mut lexer_modes = vec![];
+
+    for (mode_steps, actions) in steps.into_iter().zip(actions) {
+        let start = all.len();
+
+        all.extend(mode_steps.into_iter().map(|Step(edges, kind)| Step(
+            edges.into_iter().map(|(range, next)| (range, next + start)).collect(),
+            kind,
+        )));
+
+        lexer_modes.push(Mode::new(start, actions));
+    }
+
+    return (Lexer::modes(all, lexer_modes), kinds);
+}
+
+/// Every distinct literal used in the rules, in the order they are first used.
+fn literals(grammar: &Grammar) -> Vec<String> {
+    let mut literals = vec![];
+
+    for def in &grammar.rules {
+        find_literals(&def.expr, &mut literals);
+    }
+
+    return literals;
+}
+
+/// Add the literals in the expression that aren't in the list yet. Empty literals are left
+/// out, as they are reported by the rule they are in.
+fn find_literals(expr: &Expr, literals: &mut Vec<String>) {
+    match expr {
+        Expr::Text(text) if !text.is_empty() && !literals.contains(text) => literals.push(text.clone()),
+        Expr::Seq(exprs) | Expr::Choice(exprs) | Expr::Ordered(exprs) => {
+            for expr in exprs {
+                find_literals(expr, literals);
+            }
+        },
+        Expr::Optional(expr) | Expr::Many(expr) | Expr::Many1(expr) | Expr::And(expr) | Expr::Not(expr) =>
+            find_literals(expr, literals),
+        _ => {},
+    }
+}
+
+/// Check that a token is only made of characters, classes and literals, and turn the named
+/// classes and negations in it into classes.
+fn token_expr(def: &Definition, expr: &Expr) -> Result<Expr, GrammarError> {
+    let all = |exprs: &[Expr]| exprs.iter().map(|expr| token_expr(def, expr)).collect::<Result<Vec<_>, _>>();
+
+    return match expr {
+        Expr::Name(name) => match named_class(name) {
+            Some(class) => Ok(Expr::Class(class)),
+            None => Err(error(
+                Some(def),
+                format!("tokens can't refer to other definitions, like `{}`", name),
+            )),
+        },
+        Expr::Not(expr) => Ok(Expr::Class(CharClass::negate(char_class(def, expr)?))),
+        Expr::Seq(exprs) => Ok(Expr::Seq(all(exprs)?)),
+        Expr::Choice(exprs) => Ok(Expr::Choice(all(exprs)?)),
+        Expr::Optional(expr) => Ok(Expr::Optional(Box::new(token_expr(def, expr)?))),
+        Expr::Many(expr) => Ok(Expr::Many(Box::new(token_expr(def, expr)?))),
+        Expr::Many1(expr) => Ok(Expr::Many1(Box::new(token_expr(def, expr)?))),
+        Expr::And(_) | Expr::Ordered(_) | Expr::List(..) | Expr::Pratt(..) => Err(error(
+            Some(def),
+            "tokens can only be made of characters, classes and literals".to_string(),
+        )),
+        Expr::Text(_) | Expr::Range(..) | Expr::Class(_) => Ok(expr.clone()),
+    };
+}
+
+/// The class of characters an expression matches one of, so it can be negated.
+fn char_class(def: &Definition, expr: &Expr) -> Result<CharClass, GrammarError> {
+    return match expr {
+        Expr::Range(start, end) => Ok(CharClass::Range(*start, *end)),
+        Expr::Text(text) if text.chars().count() == 1 => Ok(CharClass::char(text.chars().next().unwrap())),
+        Expr::Class(class) => Ok(class.clone()),
+        Expr::Name(name) => named_class(name).ok_or_else(|| {
+            error(Some(def), format!("`{}` is not a class of characters", name))
+        }),
+        Expr::Choice(exprs) => Ok(CharClass::Union(
+            exprs.iter().map(|expr| char_class(def, expr)).collect::<Result<Vec<_>, _>>()?,
+        )),
+        Expr::Not(expr) => Ok(CharClass::negate(char_class(def, expr)?)),
+        _ => Err(error(Some(def), "only characters and classes can be negated".to_string())),
+    };
+}
+
+/// The class of characters with the name, if there is one.
+fn named_class(name: &str) -> Option<CharClass> {
+    return match name {
+        "XidStart" => Some(CharClass::XidStart),
+        "XidContinue" => Some(CharClass::XidContinue),
+        "Whitespace" => Some(CharClass::Whitespace),
+        "Letter" => Some(CharClass::Letter),
+        "Digit" => Some(CharClass::Digit),
+        "Any" => Some(CharClass::negate(CharClass::Union(vec![]))),
+        _ => None,
+    };
+}
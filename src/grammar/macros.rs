@@ -0,0 +1,21 @@
+/// Define a language inline, in the grammar format. Panics if the grammar is invalid.
+///
+/// The definitions are read as Rust tokens and turned back into source, so comments are
+/// dropped, and each error is reported at the macro with the text of the source it's at.
+///
+/// ```text
+/// let language = grammar! {
+///     token Name = ('a'..'z')+;
+///     token Number = ('0'..'9')+;
+///     token Punctuation = '=' | '+';
+///
+///     File = Assign*;
+///     Assign = Name "=" Number;
+/// };
+/// ```
+#[macro_export]
+macro_rules! grammar {
+    ($($source:tt)*) => {
+        $crate::grammar::inline(stringify!($($source)*), file!(), line!())
+    };
+}
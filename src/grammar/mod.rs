@@ -0,0 +1,264 @@
+//! A textual format for defining languages.
+//!
+//! A grammar is a list of definitions, each ending with a `;`. Definitions starting
+//! with `token` are compiled into a single lexer named `lexer`, the others into automata
+//! rules. The first rule is the file rule.
+//!
+//! ```text
+//! // Tokens. The longest match wins, and ties go to the one declared first.
+//! token Name = ('a'..'z' | 'A'..'Z' | '_') ('a'..'z' | 'A'..'Z' | '_' | '0'..'9')*;
+//! token Punctuation = '!'..'/' | ':'..'@' | '{'..'~';
+//! token Number = '0'..'9'+ ('.' '0'..'9'*)?;
+//!
+//! // Rules, which are sequences of tokens, literals and other rules. A literal is the
+//! // token that matches its text, or a token of its own if none does.
+//! File = EqualExpression*;
+//! EqualExpression = Name "=" Number;
+//! ```
+//!
+//! Tokens can also use the named classes `XidStart`, `XidContinue`, `Whitespace`, `Letter`,
+//! `Digit` and `Any`, and `!` for any one character not in a class, like `!('"' | '\\')`.
+//! A token can enter a mode with `-> push Mode`, or leave the one it's in with `-> pop`.
+//! The tokens of a mode are defined in a block, and are the only ones lexed in it.
+//!
+//! ```text
+//! token Quote = '"' -> push String;
+//!
+//! mode String {
+//!     token Text = !('"' | '\\')+;
+//!     token End = '"' -> pop;
+//! }
+//! ```
+//!
+//! In rules, `&X` matches if `X` does and `!X` if it doesn't, without consuming anything.
+//! Options separated by `/` instead of `|` are tried in the order they are written. A rule
+//! can also be a separated list, or an operator precedence expression over operands that
+//! are tried in order. Operators are tokens, and each makes a node of the kind after `=>`.
+//!
+//! ```text
+//! Arguments = list(Expression, Comma, min 0, trailing);
+//! Expression = pratt(Number | Group, prefix Minus 3 => Negate, infix left Plus 1 => Add);
+//! ```
+//!
+//! The same grammar can be built in Rust code with a `GrammarBuilder`, or written inline
+//! with the `grammar!` macro.
+
+// Child modules
+mod syntax;
+mod nfa;
+mod tokens;
+mod compile;
+mod regex;
+mod builder;
+mod macros;
+
+#[cfg(test)]
+mod tests;
+
+// Publish
+pub use regex::{parse_regex, regex_steps, RegexError};
+pub use builder::{and, choice, class, list, many, many1, name, not, optional, ordered, pratt, range, sep_by, seq, token, GrammarBuilder};
+
+use std::fmt;
+use crate::rules::{Associativity, CharClass, Fixity, Language, ListOptions};
+
+/// The name of the rule holding all the tokens in a compiled grammar.
+pub const LEXER: &str = "lexer";
+
+/// A line and column in the grammar source, both starting at 1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// An error in the grammar source.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GrammarError {
+    /// Where in the source the error is.
+    pub position: Position,
+
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.position.line, self.position.column, self.message)
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// An expression on the right hand side of a definition.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expr {
+    /// A reference to a token or rule.
+    Name(String),
+
+    /// A literal string of characters.
+    Text(String),
+
+    /// An inclusive range of characters.
+    Range(char, char),
+
+    /// Each expression in order.
+    Seq(Vec<Expr>),
+
+    /// Any one of the expressions.
+    Choice(Vec<Expr>),
+
+    /// The expression, or nothing.
+    Optional(Box<Expr>),
+
+    /// The expression zero or more times.
+    Many(Box<Expr>),
+
+    /// The expression one or more times.
+    Many1(Box<Expr>),
+
+    /// Any one character in a class, like the letters. Only in tokens.
+    Class(CharClass),
+
+    /// In a token, any one character not in the class of the expression. In a rule, a
+    /// predicate that matches if the expression doesn't, without consuming anything.
+    Not(Box<Expr>),
+
+    /// A predicate that matches if the expression does, without consuming anything. Only in
+    /// rules.
+    And(Box<Expr>),
+
+    /// Any one of the expressions, tried in order. Only in rules.
+    Ordered(Vec<Expr>),
+
+    /// A list of the named element, with the named separator between them. Only as the
+    /// whole of a rule.
+    List(String, String, ListOptions),
+
+    /// Operators applied to the named operands, which are tried in order. Only as the whole
+    /// of a rule.
+    Pratt(Vec<String>, Vec<PrattOperator>),
+}
+
+/// An operator in a `Pratt` expression.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrattOperator {
+    /// The name of the token the operator is.
+    pub token: String,
+
+    /// How tightly the operator binds. Higher binds tighter.
+    pub precedence: u32,
+
+    /// Where the operator goes.
+    pub fixity: Fixity,
+
+    /// The kind of node created for the operation.
+    pub kind: String,
+}
+
+impl PrattOperator {
+    /// A prefix operator.
+    pub fn prefix(token: &str, precedence: u32, kind: &str) -> PrattOperator {
+        return PrattOperator::new(token, precedence, Fixity::Prefix, kind);
+    }
+
+    /// An infix operator.
+    pub fn infix(token: &str, precedence: u32, associativity: Associativity, kind: &str) -> PrattOperator {
+        return PrattOperator::new(token, precedence, Fixity::Infix(associativity), kind);
+    }
+
+    /// A postfix operator.
+    pub fn postfix(token: &str, precedence: u32, kind: &str) -> PrattOperator {
+        return PrattOperator::new(token, precedence, Fixity::Postfix, kind);
+    }
+
+    fn new(token: &str, precedence: u32, fixity: Fixity, kind: &str) -> PrattOperator {
+        return PrattOperator { token: token.to_string(), precedence, fixity, kind: kind.to_string() };
+    }
+}
+
+/// What a token does to the lexer's mode stack.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Enter the named mode.
+    Push(String),
+
+    /// Leave the current mode.
+    Pop,
+}
+
+/// A single named definition in a grammar.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Definition {
+    /// The name of the definition, which is also the kind of node it creates.
+    pub name: String,
+
+    /// What the definition matches.
+    pub expr: Expr,
+
+    /// Where the definition starts in the source.
+    pub position: Position,
+
+    /// The lexer mode a token is in, or `None` for the default one. Always `None` for rules.
+    pub mode: Option<String>,
+
+    /// What a token does to the lexer's mode stack, if anything.
+    pub action: Option<Action>,
+}
+
+/// A parsed grammar.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Grammar {
+    /// The token definitions, in declaration order.
+    pub tokens: Vec<Definition>,
+
+    /// The rule definitions, in declaration order.
+    pub rules: Vec<Definition>,
+}
+
+impl Grammar {
+    /// Parse the source of a grammar.
+    pub fn parse(source: &str) -> Result<Grammar, GrammarError> {
+        return syntax::parse(source);
+    }
+
+    /// Compile the grammar into a language, or find every error in it.
+    pub fn compile(&self) -> Result<Language, Vec<GrammarError>> {
+        return compile::compile(self);
+    }
+}
+
+/// Parse and compile the source of a grammar into a language. Parsing stops at the first
+/// error, but compiling finds all of them.
+pub fn language(source: &str) -> Result<Language, Vec<GrammarError>> {
+    return Grammar::parse(source).map_err(|error| vec![error])?.compile();
+}
+
+/// Compile the source of a `grammar!` written at the line of the file. Panics with every error
+/// in it, each with the text it's at, as the positions are in the source the macro made.
+#[doc(hidden)]
+pub fn inline(source: &str, file: &str, line: u32) -> Language {
+    return language(source).unwrap_or_else(|errors| {
+        let errors: Vec<String> = errors.iter().map(|error| {
+            return format!("{}:{}: {}, at `{}`", file, line, error.message, snippet(source, error.position));
+        }).collect();
+
+        panic!("invalid grammar:\n{}", errors.join("\n"));
+    });
+}
+
+/// The source from the position up to the end of the definition there.
+fn snippet(source: &str, position: Position) -> String {
+    let rest: String = source.lines()
+        .skip(position.line - 1)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .chars()
+        .skip(position.column - 1)
+        .collect();
+
+    return match rest.find(';') {
+        Some(end) => rest[..=end].to_string(),
+        None => rest,
+    };
+}
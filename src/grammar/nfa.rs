@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::Expr;
+
+/// A state in the nfa.
+#[derive(Default)]
+struct State {
+    /// Edges taken by reading a symbol.
+    edges: Vec<(usize, usize)>,
+
+    /// Edges taken without reading anything.
+    empty: Vec<usize>,
+
+    /// Which definition ends at this state, if any.
+    accept: Option<usize>,
+}
+
+/// A nondeterministic finite automata, over an alphabet of numbered symbols.
+pub struct Nfa {
+    /// The states of the nfa. The first one is the start state.
+    states: Vec<State>,
+}
+
+impl Nfa {
+    /// Initializes an nfa with only a start state.
+    pub fn new() -> Nfa {
+        return Nfa { states: vec![State::default()] };
+    }
+
+    /// Adds an expression leading from the start state to an accepting state.
+    ///
+    /// The leaf function gives the symbols that a name, text or range matches.
+    pub fn add<E>(
+        &mut self,
+        expr: &Expr,
+        accept: usize,
+        leaf: &mut impl FnMut(&Expr) -> Result<Vec<usize>, E>,
+    ) -> Result<(), E> {
+        let end = self.build(expr, 0, leaf)?;
+        self.states[end].accept = Some(accept);
+
+        return Ok(());
+    }
+
+    /// Make a new state with no edges.
+    fn state(&mut self) -> usize {
+        self.states.push(State::default());
+        return self.states.len() - 1;
+    }
+
+    /// Build the states for an expression starting at the given state, and return where it ends.
+    fn build<E>(
+        &mut self,
+        expr: &Expr,
+        from: usize,
+        leaf: &mut impl FnMut(&Expr) -> Result<Vec<usize>, E>,
+    ) -> Result<usize, E> {
+        match expr {
+            Expr::Seq(items) => {
+                let mut end = from;
+                for item in items {
+                    end = self.build(item, end, leaf)?;
+                }
+
+                return Ok(end);
+            },
+            Expr::Choice(options) | Expr::Ordered(options) => {
+                let end = self.state();
+                for option in options {
+                    let option_end = self.build(option, from, leaf)?;
+                    self.states[option_end].empty.push(end);
+                }
+
+                return Ok(end);
+            },
+            Expr::Optional(expr) => {
+                // The expression could end at the head of a loop, so skipping it goes to a
+                // state of its own rather than into the loop.
+                let end = self.state();
+                let expr_end = self.build(expr, from, leaf)?;
+                self.states[from].empty.push(end);
+                self.states[expr_end].empty.push(end);
+
+                return Ok(end);
+            },
+            Expr::Many(expr) => {
+                let repeat = self.state();
+                self.states[from].empty.push(repeat);
+
+                let end = self.build(expr, repeat, leaf)?;
+                self.states[end].empty.push(repeat);
+
+                return Ok(repeat);
+            },
+            Expr::Many1(expr) => {
+                let repeat = self.state();
+                self.states[from].empty.push(repeat);
+
+                let end = self.build(expr, repeat, leaf)?;
+                let after = self.state();
+                self.states[end].empty.push(after);
+                self.states[after].empty.push(repeat);
+
+                return Ok(after);
+            },
+            _ => {
+                let end = self.state();
+                for symbol in leaf(expr)? {
+                    self.states[from].edges.push((symbol, end));
+                }
+
+                return Ok(end);
+            },
+        }
+    }
+}
+
+/// A state in the dfa.
+pub struct DfaState {
+    /// Edges taken by reading a symbol, sorted by symbol.
+    pub edges: Vec<(usize, usize)>,
+
+    /// Every definition that ends at this state, in order.
+    pub accept: Vec<usize>,
+}
+
+/// A deterministic finite automata, over an alphabet of numbered symbols.
+pub struct Dfa {
+    /// The states of the dfa. The first one is the start state.
+    pub states: Vec<DfaState>,
+}
+
+impl Nfa {
+    /// Every state reachable from the given states without reading anything.
+    fn closure(&self, mut set: Vec<usize>) -> Vec<usize> {
+        let mut todo = set.clone();
+
+        while let Some(state) = todo.pop() {
+            for &next in &self.states[state].empty {
+                if !set.contains(&next) {
+                    set.push(next);
+                    todo.push(next);
+                }
+            }
+        }
+
+        set.sort();
+        return set;
+    }
+
+    /// Turn the nfa into a dfa with the subset construction.
+    pub fn determinize(&self) -> Dfa {
+        let mut sets = vec![self.closure(vec![0])];
+        let mut ids = HashMap::new();
+        ids.insert(sets[0].clone(), 0);
+
+        let mut states = vec![];
+
+        while states.len() < sets.len() {
+            let set = sets[states.len()].clone();
+
+            // Group the targets of all the edges by symbol.
+            let mut targets: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+            for &state in &set {
+                for &(symbol, next) in &self.states[state].edges {
+                    targets.entry(symbol).or_default().push(next);
+                }
+            }
+
+            let edges = targets.into_iter().map(|(symbol, next)| {
+                let next = self.closure(next);
+
+                let id = *ids.entry(next.clone()).or_insert_with(|| {
+                    sets.push(next);
+                    sets.len() - 1
+                });
+
+                (symbol, id)
+            }).collect();
+
+            let mut accept: Vec<usize> = set.iter()
+                .filter_map(|&state| self.states[state].accept)
+                .collect();
+            accept.sort();
+            accept.dedup();
+
+            states.push(DfaState { edges, accept });
+        }
+
+        return Dfa { states };
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::str::CharIndices;
+
+use crate::document::Kind;
+use crate::rules::Step;
+
+use super::{tokens, Expr};
+
+/// A problem with the regular expressions of a lexer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RegexError {
+    /// A pattern couldn't be parsed.
+    Syntax {
+        /// The pattern with the error.
+        pattern: String,
+
+        /// The byte offset of the error in the pattern.
+        offset: usize,
+
+        /// What went wrong.
+        message: String,
+    },
+
+    /// A pattern matches the empty string, so it would never move the lexer forward.
+    Empty(String),
+
+    /// Two patterns for different kinds of token match the same text.
+    Conflict {
+        /// The pattern declared first.
+        first: String,
+
+        /// The pattern declared second.
+        second: String,
+
+        /// The shortest text both patterns match.
+        example: String,
+    },
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegexError::Syntax { pattern, offset, message } =>
+                write!(f, "in /{}/ at {}: {}", pattern, offset, message),
+            RegexError::Empty(pattern) =>
+                write!(f, "/{}/ matches the empty string", pattern),
+            RegexError::Conflict { first, second, example } =>
+                write!(f, "/{}/ and /{}/ both match {:?}", first, second, example),
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+/// Compile one regular expression per kind of token into lexer steps.
+pub fn regex_steps(patterns: &[(&str, Kind)]) -> Result<Vec<Step<RangeInclusive<char>>>, RegexError> {
+    let mut tokens = vec![];
+
+    for &(pattern, kind) in patterns {
+        tokens.push((parse_regex(pattern)?, kind));
+    }
+
+    let compiled = tokens::compile(&tokens);
+
+    if let Some(conflict) = compiled.conflicts.first() {
+        return Err(RegexError::Conflict {
+            first: patterns[conflict.first].0.to_string(),
+            second: patterns[conflict.second].0.to_string(),
+            example: conflict.example.clone(),
+        });
+    }
+
+    if compiled.nullable {
+        let empty = patterns.iter().zip(&tokens)
+            .find(|(_, (expr, _))| tokens::compile(&[(expr.clone(), Kind::ERROR)]).nullable)
+            .unwrap();
+
+        return Err(RegexError::Empty(empty.0.0.to_string()));
+    }
+
+    return Ok(compiled.steps);
+}
+
+/// Parse a regular expression.
+///
+/// Supports literals, `.`, classes like `[a-z_]` and `[^"]`, the escapes `\d`, `\w`, `\s`,
+/// `\n`, `\t`, `\r` and `\u{XXXX}`, grouping with `(...)`, `|`, `*`, `+` and `?`.
+pub fn parse_regex(pattern: &str) -> Result<Expr, RegexError> {
+    let mut reader = Reader {
+        pattern,
+        chars: pattern.char_indices().peekable(),
+    };
+
+    let expr = reader.choice()?;
+
+    if let Some((offset, chr)) = reader.chars.next() {
+        return Err(reader.error(offset, format!("unexpected `{}`", chr)));
+    }
+
+    return Ok(expr);
+}
+
+/// Reads a pattern one character at a time.
+struct Reader<'a> {
+    pattern: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Reader<'_> {
+    /// Build an error at the given offset.
+    fn error(&self, offset: usize, message: String) -> RegexError {
+        return RegexError::Syntax {
+            pattern: self.pattern.to_string(),
+            offset, message,
+        };
+    }
+
+    /// Look at the next character.
+    fn peek(&mut self) -> Option<char> {
+        return self.chars.peek().map(|(_, chr)| *chr);
+    }
+
+    /// The offset of the next character.
+    fn offset(&mut self) -> usize {
+        return self.chars.peek().map_or(self.pattern.len(), |(offset, _)| *offset);
+    }
+
+    /// Parse alternatives separated by `|`.
+    fn choice(&mut self) -> Result<Expr, RegexError> {
+        let mut options = vec![self.sequence()?];
+
+        while self.peek() == Some('|') {
+            self.chars.next();
+            options.push(self.sequence()?);
+        }
+
+        return Ok(if options.len() == 1 { options.pop().unwrap() } else { Expr::Choice(options) });
+    }
+
+    /// Parse a list of expressions that follow one another.
+    fn sequence(&mut self) -> Result<Expr, RegexError> {
+        let mut items = vec![];
+
+        while !matches!(self.peek(), Some('|') | Some(')') | None) {
+            items.push(self.postfix()?);
+        }
+
+        return Ok(if items.len() == 1 { items.pop().unwrap() } else { Expr::Seq(items) });
+    }
+
+    /// Parse an expression followed by any number of `*`, `+` or `?`.
+    fn postfix(&mut self) -> Result<Expr, RegexError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            expr = match self.peek() {
+                Some('*') => Expr::Many(Box::new(expr)),
+                Some('+') => Expr::Many1(Box::new(expr)),
+                Some('?') => Expr::Optional(Box::new(expr)),
+                _ => return Ok(expr),
+            };
+
+            self.chars.next();
+        }
+    }
+
+    /// Parse a character, class or group.
+    fn primary(&mut self) -> Result<Expr, RegexError> {
+        let offset = self.offset();
+
+        return match self.chars.next().map(|(_, chr)| chr) {
+            Some('(') => {
+                let expr = self.choice()?;
+
+                if self.peek() != Some(')') {
+                    let offset = self.offset();
+                    return Err(self.error(offset, "expected `)`".to_string()));
+                }
+
+                self.chars.next();
+                Ok(expr)
+            },
+            Some('[') => self.class(),
+            Some('.') => Ok(choice(tokens::complement(&[('\n', '\n')]))),
+            Some('\\') => Ok(choice(self.escape(offset)?)),
+            Some(chr @ '*') | Some(chr @ '+') | Some(chr @ '?') =>
+                Err(self.error(offset, format!("nothing to repeat before `{}`", chr))),
+            Some(chr) => Ok(Expr::Range(chr, chr)),
+            None => Err(self.error(offset, "unexpected end of pattern".to_string())),
+        };
+    }
+
+    /// Parse the rest of a class after the `[`.
+    fn class(&mut self) -> Result<Expr, RegexError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.chars.next();
+        }
+
+        let mut ranges = vec![];
+
+        loop {
+            let offset = self.offset();
+
+            let start = match self.chars.next().map(|(_, chr)| chr) {
+                Some(']') if !ranges.is_empty() => break,
+                Some('\\') => {
+                    let escaped = self.escape(offset)?;
+
+                    if escaped.len() > 1 {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+
+                    escaped[0].0
+                },
+                Some(chr) => chr,
+                None => return Err(self.error(offset, "unterminated class, expected `]`".to_string())),
+            };
+
+            // A `-` at the end of the class is just a `-`.
+            let mut lookahead = self.chars.clone();
+            if self.peek() == Some('-') && !matches!(lookahead.nth(1), Some((_, ']')) | None) {
+                self.chars.next();
+
+                let offset = self.offset();
+                let end = match self.chars.next().map(|(_, chr)| chr) {
+                    Some('\\') => self.escape(offset)?[0].0,
+                    Some(chr) => chr,
+                    None => return Err(self.error(offset, "unterminated class, expected `]`".to_string())),
+                };
+
+                if end < start {
+                    return Err(self.error(offset, format!("the range {:?}-{:?} is empty", start, end)));
+                }
+
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+
+        if negated {
+            ranges = tokens::complement(&ranges);
+        }
+
+        return Ok(choice(ranges));
+    }
+
+    /// Parse the rest of an escape after the `\`, into the ranges it matches.
+    fn escape(&mut self, offset: usize) -> Result<Vec<(char, char)>, RegexError> {
+        return match self.chars.next().map(|(_, chr)| chr) {
+            Some('d') => Ok(vec![('0', '9')]),
+            Some('w') => Ok(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]),
+            Some('s') => Ok(vec![('\t', '\r'), (' ', ' ')]),
+            Some('n') => Ok(vec![('\n', '\n')]),
+            Some('t') => Ok(vec![('\t', '\t')]),
+            Some('r') => Ok(vec![('\r', '\r')]),
+            Some('u') => {
+                let mut digits = String::new();
+
+                if self.peek() == Some('{') {
+                    self.chars.next();
+                    while let Some(chr) = self.peek().filter(|chr| *chr != '}') {
+                        digits.push(chr);
+                        self.chars.next();
+                    }
+                    self.chars.next();
+                }
+
+                match u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32) {
+                    Some(chr) => Ok(vec![(chr, chr)]),
+                    None => Err(self.error(offset, "expected a unicode escape like `\\u{2014}`".to_string())),
+                }
+            },
+            Some(chr) if !chr.is_alphanumeric() => Ok(vec![(chr, chr)]),
+            Some(chr) => Err(self.error(offset, format!("unknown escape `\\{}`", chr))),
+            None => Err(self.error(offset, "unexpected end of pattern".to_string())),
+        };
+    }
+}
+
+/// An expression matching any of the ranges.
+fn choice(ranges: Vec<(char, char)>) -> Expr {
+    let mut options: Vec<Expr> = ranges.into_iter().map(|(start, end)| Expr::Range(start, end)).collect();
+
+    return if options.len() == 1 { options.pop().unwrap() } else { Expr::Choice(options) };
+}
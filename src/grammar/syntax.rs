@@ -0,0 +1,490 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::rules::{Associativity, Fixity, ListOptions};
+
+use super::{Action, Definition, Expr, Grammar, GrammarError, Position, PrattOperator};
+
+/// Parse the source of a grammar.
+pub fn parse(source: &str) -> Result<Grammar, GrammarError> {
+    let mut reader = Reader {
+        chars: source.chars().peekable(),
+        position: Position { line: 1, column: 1 },
+    };
+
+    let mut grammar = Grammar::default();
+
+    // The mode block we are in, if any.
+    let mut mode: Option<String> = None;
+
+    while reader.skip_space() {
+        if mode.is_some() && reader.peek() == Some('}') {
+            reader.next();
+            mode = None;
+            continue;
+        }
+
+        let position = reader.position;
+        let mut name = reader.name()?;
+
+        // A rule can still be called `mode`.
+        if name == "mode" && reader.skip_space() && reader.peek() != Some('=') {
+            if mode.is_some() {
+                return Err(GrammarError { position, message: "modes can't be nested".to_string() });
+            }
+
+            reader.skip_space();
+            mode = Some(reader.name()?);
+            reader.expect('{')?;
+            continue;
+        }
+
+        let is_token = name == "token";
+        if is_token {
+            reader.skip_space();
+            name = reader.name()?;
+        } else if mode.is_some() {
+            return Err(GrammarError { position, message: "only tokens can be defined in a mode".to_string() });
+        }
+
+        if grammar.tokens.iter().chain(&grammar.rules).any(|def| def.name == name) {
+            return Err(GrammarError {
+                position,
+                message: format!("`{}` is defined more than once", name),
+            });
+        }
+
+        reader.expect('=')?;
+        let expr = if is_token { reader.choice()? } else { reader.rule()? };
+        let action = if is_token { reader.action()? } else { None };
+        reader.expect(';')?;
+
+        let definition = Definition {
+            name, expr, position, action,
+            mode: if is_token { mode.clone() } else { None },
+        };
+
+        if is_token {
+            grammar.tokens.push(definition);
+        } else {
+            grammar.rules.push(definition);
+        }
+    }
+
+    if let Some(mode) = mode {
+        return reader.error(format!("expected `}}` to end the mode `{}`, found the end of the grammar", mode));
+    }
+
+    return Ok(grammar);
+}
+
+/// Reads the grammar source one character at a time, keeping track of the position.
+#[derive(Clone)]
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+}
+
+impl Reader<'_> {
+    /// Look at the next character.
+    fn peek(&mut self) -> Option<char> {
+        return self.chars.peek().copied();
+    }
+
+    /// Eat the next character.
+    fn next(&mut self) -> Option<char> {
+        let chr = self.chars.next()?;
+
+        if chr == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+
+        return Some(chr);
+    }
+
+    /// Build an error at the current position.
+    fn error<T>(&self, message: String) -> Result<T, GrammarError> {
+        return Err(GrammarError { position: self.position, message });
+    }
+
+    /// Skip whitespace and comments. Returns false if the end of the source was reached.
+    fn skip_space(&mut self) -> bool {
+        loop {
+            match self.peek() {
+                Some(chr) if chr.is_whitespace() => {
+                    self.next();
+                },
+                Some('/') if self.chars.clone().nth(1) == Some('/') => {
+                    while !matches!(self.next(), Some('\n') | None) {}
+                },
+                Some(_) => return true,
+                None => return false,
+            }
+        }
+    }
+
+    /// Skip whitespace, then eat the given character or fail.
+    fn expect(&mut self, expected: char) -> Result<(), GrammarError> {
+        self.skip_space();
+
+        return match self.peek() {
+            Some(chr) if chr == expected => {
+                self.next();
+                Ok(())
+            },
+            Some(chr) => self.error(format!("expected `{}`, found `{}`", expected, chr)),
+            None => self.error(format!("expected `{}`, found the end of the grammar", expected)),
+        };
+    }
+
+    /// Read an identifier.
+    fn name(&mut self) -> Result<String, GrammarError> {
+        let mut name = String::new();
+
+        while let Some(chr) = self.peek() {
+            if chr.is_alphanumeric() || chr == '_' {
+                name.push(chr);
+                self.next();
+            } else {
+                break;
+            }
+        }
+
+        if name.is_empty() || name.starts_with(|chr: char| chr.is_numeric()) {
+            return match self.peek() {
+                Some(chr) => self.error(format!("expected a name, found `{}`", chr)),
+                None => self.error("expected a name, found the end of the grammar".to_string()),
+            };
+        }
+
+        return Ok(name);
+    }
+
+    /// Skip whitespace, then read an identifier.
+    fn word(&mut self) -> Result<String, GrammarError> {
+        self.skip_space();
+        return self.name();
+    }
+
+    /// Skip whitespace, then read the name of a rule or token where a literal can't be used.
+    fn reference(&mut self) -> Result<String, GrammarError> {
+        self.skip_space();
+
+        if self.peek() == Some('"') {
+            return self.error("only names can be used here, so give the literal a token of its own".to_string());
+        }
+
+        return self.name();
+    }
+
+    /// Skip whitespace, then read a whole number.
+    fn number(&mut self) -> Result<usize, GrammarError> {
+        self.skip_space();
+
+        let mut digits = String::new();
+        while let Some(chr) = self.peek().filter(|chr| chr.is_ascii_digit()) {
+            digits.push(chr);
+            self.next();
+        }
+
+        return match digits.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => self.error("expected a number".to_string()),
+        };
+    }
+
+    /// Read a possibly escaped character inside of a quoted literal.
+    fn escaped(&mut self, quote: char) -> Result<char, GrammarError> {
+        let position = self.position;
+
+        let chr = match self.next() {
+            Some('\\') => match self.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('\'') => '\'',
+                Some('"') => '"',
+                Some('u') => self.unicode()?,
+                Some(chr) => return Err(GrammarError {
+                    position,
+                    message: format!("unknown escape `\\{}`", chr),
+                }),
+                None => '\\',
+            },
+            Some('\n') | None => return Err(GrammarError {
+                position,
+                message: format!("unterminated literal, expected `{}`", quote),
+            }),
+            Some(chr) => chr,
+        };
+
+        return Ok(chr);
+    }
+
+    /// Read the `{XXXX}` part of a unicode escape.
+    fn unicode(&mut self) -> Result<char, GrammarError> {
+        self.expect('{')?;
+
+        let position = self.position;
+        let mut digits = String::new();
+        while let Some(chr) = self.peek().filter(|chr| chr.is_ascii_hexdigit()) {
+            digits.push(chr);
+            self.next();
+        }
+
+        self.expect('}')?;
+
+        return u32::from_str_radix(&digits, 16).ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| GrammarError {
+                position,
+                message: format!("`{}` is not a valid unicode escape", digits),
+            });
+    }
+
+    /// Read a single quoted character, like `'a'`.
+    fn character(&mut self) -> Result<char, GrammarError> {
+        self.skip_space();
+        self.expect('\'')?;
+        let chr = self.escaped('\'')?;
+        self.expect('\'')?;
+
+        return Ok(chr);
+    }
+}
+
+impl Reader<'_> {
+    /// Parse the right hand side of a rule, which can be a list or pratt expression.
+    fn rule(&mut self) -> Result<Expr, GrammarError> {
+        self.skip_space();
+
+        // They are only special when followed by a `(`, so rules can still have the names.
+        let mut lookahead = self.clone();
+        let name = lookahead.name().ok().filter(|_| lookahead.peek() == Some('('));
+
+        match name.as_deref() {
+            Some("list") => {
+                *self = lookahead;
+                self.next();
+                return self.list();
+            },
+            Some("pratt") => {
+                *self = lookahead;
+                self.next();
+                return self.pratt();
+            },
+            _ => return self.choice(),
+        }
+    }
+
+    /// Parse the rest of a list after the `(`.
+    fn list(&mut self) -> Result<Expr, GrammarError> {
+        let element = self.reference()?;
+        self.expect(',')?;
+        let separator = self.reference()?;
+
+        let mut options = ListOptions::default();
+
+        while self.skip_space() && self.peek() == Some(',') {
+            self.next();
+
+            match self.word()?.as_str() {
+                "trailing" => options.trailing = true,
+                "min" => options.min = self.number()?,
+                other => return self.error(format!("expected `trailing` or `min`, found `{}`", other)),
+            }
+        }
+
+        self.expect(')')?;
+
+        return Ok(Expr::List(element, separator, options));
+    }
+
+    /// Parse the rest of a pratt expression after the `(`.
+    fn pratt(&mut self) -> Result<Expr, GrammarError> {
+        let mut operands = vec![self.reference()?];
+
+        while self.skip_space() && self.peek() == Some('|') {
+            self.next();
+            operands.push(self.reference()?);
+        }
+
+        let mut operators = vec![];
+
+        while self.skip_space() && self.peek() == Some(',') {
+            self.next();
+
+            let fixity = match self.word()?.as_str() {
+                "prefix" => Fixity::Prefix,
+                "postfix" => Fixity::Postfix,
+                "infix" => match self.word()?.as_str() {
+                    "left" => Fixity::Infix(Associativity::Left),
+                    "right" => Fixity::Infix(Associativity::Right),
+                    other => return self.error(format!("expected `left` or `right`, found `{}`", other)),
+                },
+                other => return self.error(format!("expected `prefix`, `infix` or `postfix`, found `{}`", other)),
+            };
+
+            let token = self.reference()?;
+            let precedence = self.number()? as u32;
+            self.expect('=')?;
+            self.expect('>')?;
+            let kind = self.word()?;
+
+            operators.push(PrattOperator { token, precedence, fixity, kind });
+        }
+
+        self.expect(')')?;
+
+        return Ok(Expr::Pratt(operands, operators));
+    }
+
+    /// Parse what a token does to the mode stack, if anything, like `-> push String`.
+    fn action(&mut self) -> Result<Option<Action>, GrammarError> {
+        if !self.skip_space() || self.peek() != Some('-') {
+            return Ok(None);
+        }
+
+        self.next();
+        self.expect('>')?;
+
+        return match self.word()?.as_str() {
+            "push" => Ok(Some(Action::Push(self.word()?))),
+            "pop" => Ok(Some(Action::Pop)),
+            other => self.error(format!("expected `push` or `pop`, found `{}`", other)),
+        };
+    }
+
+    /// Parse alternatives separated by `|`, or by `/` if they are tried in order.
+    fn choice(&mut self) -> Result<Expr, GrammarError> {
+        let mut options = vec![self.sequence()?];
+        let mut separator = None;
+
+        while self.skip_space() && matches!(self.peek(), Some('|') | Some('/')) {
+            let chr = self.next();
+
+            if separator.is_some() && separator != chr {
+                return self.error("`|` and `/` can't be mixed without parentheses".to_string());
+            }
+
+            separator = chr;
+            options.push(self.sequence()?);
+        }
+
+        return Ok(match separator {
+            None => options.pop().unwrap(),
+            Some('/') => Expr::Ordered(options),
+            Some(_) => Expr::Choice(options),
+        });
+    }
+
+    /// Parse a list of expressions that follow one another.
+    fn sequence(&mut self) -> Result<Expr, GrammarError> {
+        let mut items = vec![];
+
+        while self.skip_space() && !matches!(self.peek(), Some('|') | Some('/') | Some(')') | Some(';') | Some('-')) {
+            items.push(self.prefix()?);
+        }
+
+        if items.is_empty() {
+            return match self.peek() {
+                Some(chr) => self.error(format!("expected an expression, found `{}`", chr)),
+                None => self.error("expected an expression, found the end of the grammar".to_string()),
+            };
+        }
+
+        return Ok(if items.len() == 1 { items.pop().unwrap() } else { Expr::Seq(items) });
+    }
+
+    /// Parse an expression, which can start with a `&` or `!`.
+    fn prefix(&mut self) -> Result<Expr, GrammarError> {
+        match self.peek() {
+            Some('&') => {
+                self.next();
+                self.skip_space();
+                return Ok(Expr::And(Box::new(self.prefix()?)));
+            },
+            Some('!') => {
+                self.next();
+                self.skip_space();
+                return Ok(Expr::Not(Box::new(self.prefix()?)));
+            },
+            _ => return self.postfix(),
+        }
+    }
+
+    /// Parse an expression followed by any number of `*`, `+` or `?`.
+    fn postfix(&mut self) -> Result<Expr, GrammarError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            self.skip_space();
+
+            expr = match self.peek() {
+                Some('*') => Expr::Many(Box::new(expr)),
+                Some('+') => Expr::Many1(Box::new(expr)),
+                Some('?') => Expr::Optional(Box::new(expr)),
+                _ => return Ok(expr),
+            };
+
+            self.next();
+        }
+    }
+
+    /// Parse a name, literal, character range or parenthesized expression.
+    fn primary(&mut self) -> Result<Expr, GrammarError> {
+        let position = self.position;
+
+        match self.peek() {
+            Some('(') => {
+                self.next();
+                let expr = self.choice()?;
+                self.expect(')')?;
+
+                return Ok(expr);
+            },
+            Some('"') => {
+                self.next();
+
+                let mut text = String::new();
+                while self.peek() != Some('"') {
+                    text.push(self.escaped('"')?);
+                }
+                self.next();
+
+                if text.is_empty() {
+                    return Err(GrammarError { position, message: "empty literal".to_string() });
+                }
+
+                return Ok(Expr::Text(text));
+            },
+            Some('\'') => {
+                let start = self.character()?;
+
+                self.skip_space();
+                if self.peek() != Some('.') {
+                    return Ok(Expr::Range(start, start));
+                }
+
+                self.expect('.')?;
+                self.expect('.')?;
+                let end = self.character()?;
+
+                if end < start {
+                    return Err(GrammarError {
+                        position,
+                        message: format!("the range {:?}..{:?} is empty", start, end),
+                    });
+                }
+
+                return Ok(Expr::Range(start, end));
+            },
+            _ => return Ok(Expr::Name(self.name()?)),
+        }
+    }
+}
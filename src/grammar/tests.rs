@@ -0,0 +1,372 @@
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use crate::document::{Document, Node};
+use crate::rules::{Language, LanguageBuilder, Step};
+
+use super::regex_steps;
+
+/// Write out the kinds and texts of a tree, like `Assign(Name(x) "=" Number(1))`.
+fn tree(document: &Document, node: &Rc<Node>) -> String {
+    let name = document.lang.kinds.name(node.kind);
+
+    if node.subs.is_empty() {
+        return format!("{}({})", name, document.node_text(node));
+    }
+
+    let subs: Vec<String> = node.subs.iter().map(|sub| tree(document, sub)).collect();
+    return format!("{}({})", name, subs.join(" "));
+}
+
+/// Parse the text, and write out the tree.
+fn parse(language: &Language, text: &str) -> String {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+
+    return tree(&document, &document.root);
+}
+
+/// Parse the text, make the edit, and write out the tree.
+fn edit(language: &Language, text: &str, span: (usize, usize), edit: &str) -> String {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+    document.edit(span, edit);
+
+    return tree(&document, &document.root);
+}
+
+#[test]
+fn tokens_and_literals_of_the_same_kind() {
+    let language = crate::grammar! {
+        token Name = ('a'..'z')+;
+        token Space = ' '+;
+
+        File = Stmt*;
+        Stmt = "let" Space Name Space Name | Name Space Name;
+    };
+
+    // `let` is a Name too, so it could start either way.
+    assert_eq!(parse(&language, "let foo"), "File(Stmt(Name(let) Space( ) Name(foo)))");
+    assert_eq!(parse(&language, "let foo bar"), "File(Stmt(Name(let) Space( ) Name(foo) Space( ) Name(bar)))");
+    assert_eq!(parse(&language, "foo bar"), "File(Stmt(Name(foo) Space( ) Name(bar)))");
+}
+
+/// Compile the grammar, and write out the position and message of each error.
+fn errors(source: &str) -> Vec<String> {
+    return match crate::grammar::language(source) {
+        Ok(_) => vec![],
+        Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    };
+}
+
+#[test]
+fn every_error_at_its_definition() {
+    assert_eq!(errors("token Name = ('a'..'z')+;\nFile = Name Missing;\nOther = Name | Unknown;"), vec![
+        "2:1: `Missing` is not defined",
+        "3:1: `Unknown` is not defined",
+    ]);
+
+    assert_eq!(errors("token Name = ('a'..'z')+;\nFile = Loop Same;\nLoop = Maybe*;\nMaybe = Name?;\nSame = Same | Name;"), vec![
+        "3:1: the rule `Loop` can loop forever at step 1 without consuming anything",
+        "5:1: the rule `Same` can be made of itself without consuming anything",
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "src/grammar/tests.rs:")]
+fn errors_in_the_macro_say_where_the_macro_is() {
+    crate::grammar! {
+        token Name = ('a'..'z')+;
+        File = Name Missing;
+    };
+}
+
+#[test]
+#[should_panic(expected = "`Missing` is not defined, at `File = Name Missing;`")]
+fn errors_in_the_macro_show_the_definition() {
+    crate::grammar! {
+        token Name = ('a'..'z')+;
+        File = Name Missing;
+    };
+}
+
+#[test]
+fn tokens_take_the_longest_match() {
+    let language = crate::grammar! {
+        token If = "if";
+        token Name = ('a'..'z')+;
+        token Number = ('0'..'9')+ ('.' ('0'..'9')+)?;
+        token Dot = '.';
+        token Space = ' '+;
+
+        File = (If | Name | Number | Dot | Space)*;
+    };
+
+    // Ties go to the token declared first, and a number without digits after the `.` ends
+    // before it.
+    assert_eq!(
+        parse(&language, "if iffy 1.5 1."),
+        "File(If(if) Space( ) Name(iffy) Space( ) Number(1.5) Space( ) Number(1) Dot(.))",
+    );
+}
+
+#[test]
+fn regular_expressions_are_minimized() {
+    let mut language = LanguageBuilder::new();
+    let name = language.kind("Name");
+    let keyword = language.kind("Keyword");
+
+    assert_eq!(regex_steps(&[("(a|b)*c", name)]).unwrap().len(), 2);
+    assert_eq!(regex_steps(&[("ab|cb", name)]).unwrap().len(), 3);
+
+    assert_eq!(
+        regex_steps(&[("[a-z]+", name), ("if", keyword)]).err().unwrap().to_string(),
+        "/[a-z]+/ and /if/ both match \"if\"",
+    );
+}
+
+/// The length of the longest text at the start of the input the regex steps match.
+fn longest_match(steps: &[Step<RangeInclusive<char>>], input: &str) -> Option<usize> {
+    let mut state = 0;
+    let mut longest = steps[0].1.map(|_| 0);
+
+    for (offset, chr) in input.char_indices() {
+        match steps[state].0.iter().find(|(range, _)| range.contains(&chr)) {
+            Some(&(_, next)) => state = next,
+            None => break,
+        }
+
+        if steps[state].1.is_some() {
+            longest = Some(offset + chr.len_utf8());
+        }
+    }
+
+    return longest;
+}
+
+#[test]
+fn optional_repetition() {
+    let mut language = LanguageBuilder::new();
+    let name = language.kind("Name");
+
+    // Skipping the optional part mustn't lead into the repetition at its end.
+    let steps = regex_steps(&[(r"x(\.y*)?", name)]).unwrap();
+    assert_eq!(longest_match(&steps, "x.yy"), Some(4));
+    assert_eq!(longest_match(&steps, "xy"), Some(1));
+    assert_eq!(longest_match(&steps, "y"), None);
+
+    let tokens = crate::grammar! {
+        token Dotted = 'x' ('.' 'y'*)?;
+        token Y = 'y';
+
+        File = (Dotted | Y)*;
+    };
+
+    assert_eq!(parse(&tokens, "x.yyxy"), "File(Dotted(x.yy) Dotted(x) Y(y))");
+
+    let rules = crate::grammar! {
+        token X = 'x';
+        token Y = 'y';
+        token Z = 'z';
+
+        File = (X Y*)? Z;
+    };
+
+    assert_eq!(parse(&rules, "xyyz"), "File(X(x) Y(y) Y(y) Z(z))");
+    assert_eq!(parse(&rules, "z"), "File(Z(z))");
+
+    let mut document = Document::new(&rules);
+    document.edit((0, 0), "yz");
+    assert!(!document.diagnostics().is_empty());
+}
+
+#[test]
+fn unicode_and_negated_classes() {
+    let language = crate::grammar! {
+        token Name = XidStart XidContinue*;
+        token Number = Digit+;
+        token Space = Whitespace+;
+        token Text = '"' (!('"' | '\n'))* '"';
+
+        File = (Name | Number | Space | Text)*;
+    };
+
+    assert_eq!(
+        parse(&language, "héllo\u{3000}\"a b\"\u{663}"),
+        "File(Name(héllo) Space(\u{3000}) Text(\"a b\") Number(\u{663}))",
+    );
+}
+
+#[test]
+fn predicates() {
+    let language = crate::grammar! {
+        token Name = ('a'..'z')+;
+        token Space = ' '+;
+        token Punctuation = '(' | ')';
+
+        File = (Call | Identifier | Keyword | Space)*;
+        Call = Name &"(" "(" ")";
+        Identifier = !Keyword Name;
+        Keyword = "if" | "else";
+    };
+
+    assert_eq!(
+        parse(&language, "if f() x else"),
+        "File(Keyword(Name(if)) Space( ) Call(Name(f) Punctuation(() Punctuation())) Space( ) \
+            Identifier(Name(x)) Space( ) Keyword(Name(else)))",
+    );
+}
+
+#[test]
+fn ordered_choice() {
+    let ordered = crate::grammar! {
+        token Name = ('a'..'z')+;
+        token Punctuation = '.' | ';';
+
+        File = Item*;
+        Item = Short ";" / Long ";";
+        Short = Name;
+        Long = Name "." Name;
+    };
+
+    let unordered = crate::grammar! {
+        token Name = ('a'..'z')+;
+        token Punctuation = '.' | ';';
+
+        File = Item*;
+        Item = Short ";" | Long ";";
+        Short = Name;
+        Long = Name "." Name;
+    };
+
+    // Only the ordered choice goes back to try the long one after the short one.
+    assert_eq!(
+        parse(&ordered, "a.b;"),
+        "File(Item(Long(Name(a) Punctuation(.) Name(b)) Punctuation(;)))",
+    );
+
+    let mut document = Document::new(&unordered);
+    document.edit((0, 0), "a.b;");
+    assert!(!document.diagnostics().is_empty());
+}
+
+#[test]
+fn lexer_modes() {
+    let language = crate::grammar! {
+        token Name = ('a'..'z')+;
+        token Space = ' '+;
+        token Quote = '"' -> push String;
+
+        mode String {
+            token Text = (!('"' | '\\'))+;
+            token Escape = '\\' Any;
+            token Close = '"' -> pop;
+        }
+
+        File = (Name | Space | String)*;
+        String = Quote (Text | Escape)* Close;
+    };
+
+    assert_eq!(
+        parse(&language, "a \"b c\\\"d\" e"),
+        "File(Name(a) Space( ) String(Quote(\") Text(b c) Escape(\\\") Text(d) Close(\")) Space( ) Name(e))",
+    );
+}
+
+#[test]
+fn lists_and_operators() {
+    let language = crate::grammar! {
+        token Number = ('0'..'9')+;
+        token Plus = '+';
+        token Star = '*';
+        token Minus = '-';
+        token Comma = ',';
+        token Open = '(';
+        token Close = ')';
+
+        File = Arguments;
+        Arguments = list(Expression, Comma, min 0, trailing);
+        Expression = pratt(Number | Group, prefix Minus 3 => Negate, infix left Plus 1 => Add, infix left Star 2 => Multiply);
+        Group = Open Expression Close;
+    };
+
+    assert_eq!(
+        parse(&language, "1+2*3,-(4),"),
+        "File(Arguments(\
+            Expression(Add(Number(1) Plus(+) Multiply(Number(2) Star(*) Number(3)))) Comma(,) \
+            Expression(Negate(Minus(-) Group(Open(() Expression(Number(4)) Close())))) Comma(,)))",
+    );
+
+    assert_eq!(parse(&language, ""), "File(Arguments())");
+}
+
+/// Parse the text, make the edit, and write out the diagnostics.
+fn edit_diagnostics(language: &Language, text: &str, span: (usize, usize), edit: &str) -> Vec<String> {
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+    document.edit(span, edit);
+
+    return document.diagnostics().iter().map(|diagnostic| diagnostic.to_string()).collect();
+}
+
+/// Parse the text, and write out the diagnostics.
+fn diagnostics(language: &Language, text: &str) -> Vec<String> {
+    return edit_diagnostics(language, "", (0, 0), text);
+}
+
+#[test]
+fn diagnostics_are_the_same_after_reusing_nodes() {
+    let language = crate::grammar! {
+        token Number = ('0'..'9')+;
+        token Plus = '+';
+        token Star = '*';
+        token Minus = '-';
+        token Comma = ',';
+        token Open = '(';
+        token Close = ')';
+
+        File = Arguments;
+        Arguments = list(Expression, Comma, min 0, trailing);
+        Expression = pratt(Number | Group, prefix Minus 3 => Negate, infix left Plus 1 => Add, infix left Star 2 => Multiply);
+        Group = Open Expression Close;
+    };
+
+    assert_eq!(edit_diagnostics(&language, "1(1+", (0, 1), ""), diagnostics(&language, "(1+"));
+    assert_eq!(edit_diagnostics(&language, "(1+", (0, 0), "1"), diagnostics(&language, "1(1+"));
+    assert_eq!(edit_diagnostics(&language, "(1+2,(3", (6, 7), "4*"), diagnostics(&language, "(1+2,(4*"));
+}
+
+#[test]
+fn left_recursion() {
+    let language = crate::grammar! {
+        token Number = ('0'..'9')+;
+        token Punctuation = '+' | '-';
+
+        File = Sum;
+        Sum = Sum "+" Number | Sum "-" Number | Number;
+    };
+
+    assert_eq!(
+        parse(&language, "1+2-3"),
+        "File(Sum(Sum(Sum(Number(1)) Punctuation(+) Number(2)) Punctuation(-) Number(3)))",
+    );
+
+    // The seed grows again from the edit.
+    assert_eq!(edit(&language, "1+2-3", (2, 3), "4+5"), parse(&language, "1+4+5-3"));
+    assert_eq!(edit(&language, "1+2-3", (0, 2), ""), parse(&language, "2-3"));
+}
+
+#[test]
+fn left_recursion_after_the_seed() {
+    let language = crate::grammar! {
+        token Number = ('0'..'9')+;
+        token Punctuation = '+' | '-';
+
+        File = Sum;
+        Sum = Sum "+" Number | Sum "-" Number | Number;
+    };
+
+    // The seeds the result grew from end before the edit, but are only a part of the result.
+    assert_eq!(edit(&language, "1+2", (3, 3), "+3"), parse(&language, "1+2+3"));
+    assert_eq!(edit(&language, "1-1", (3, 3), ""), parse(&language, "1-1"));
+    assert_eq!(edit(&language, "1+2;", (3, 3), "+3"), parse(&language, "1+2+3;"));
+}
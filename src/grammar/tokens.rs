@@ -0,0 +1,263 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+
+use crate::document::Kind;
+use crate::rules::Step;
+
+use super::nfa::{Dfa, Nfa};
+use super::Expr;
+
+/// Two tokens of different kinds that can match the same text.
+pub struct Conflict {
+    /// The token declared first.
+    pub first: usize,
+
+    /// The token declared second.
+    pub second: usize,
+
+    /// The shortest text both tokens match.
+    pub example: String,
+}
+
+/// The lexer steps compiled from a list of tokens.
+pub struct Tokens {
+    /// The minimized steps. When several tokens match the same text the first one wins.
+    pub steps: Vec<Step<RangeInclusive<char>>>,
+
+    /// Every pair of tokens that can match the same text.
+    pub conflicts: Vec<Conflict>,
+
+    /// Does any token match the empty string?
+    pub nullable: bool,
+}
+
+/// Compile token expressions into the steps of a lexer.
+///
+/// The expressions can only be made of ranges, classes, literals and their combinations.
+pub fn compile(tokens: &[(Expr, Kind)]) -> Tokens {
+    let tokens: Vec<(Expr, Kind)> = tokens.iter().map(|(expr, kind)| (spell(expr), *kind)).collect();
+
+    // Split every range used into disjoint intervals, which are the symbols of the nfa.
+    let mut bounds = BTreeSet::new();
+    for (expr, _) in &tokens {
+        ranges(expr, &mut |start, end| {
+            bounds.insert(start as u32);
+            bounds.insert(end as u32 + 1);
+        });
+    }
+
+    let bounds: Vec<u32> = bounds.into_iter().collect();
+    let intervals: Vec<(u32, u32)> = bounds.windows(2).map(|pair| (pair[0], pair[1] - 1)).collect();
+
+    let mut nfa = Nfa::new();
+    for (i, (expr, _)) in tokens.iter().enumerate() {
+        let result: Result<(), ()> = nfa.add(expr, i, &mut |expr: &Expr| {
+            if let Expr::Range(start, end) = expr {
+                return Ok(intervals.iter()
+                    .enumerate()
+                    .filter(|(_, (a, b))| *start as u32 <= *a && *b <= *end as u32)
+                    .map(|(symbol, _)| symbol)
+                    .collect());
+            }
+
+            panic!("tokens can only be made of ranges and literals");
+        });
+
+        result.unwrap();
+    }
+
+    let dfa = nfa.determinize();
+
+    let conflicts = conflicts(&dfa, &tokens, &intervals);
+
+    // Earlier tokens take priority over later ones.
+    let accept: Vec<Option<Kind>> = dfa.states.iter()
+        .map(|state| state.accept.first().map(|&token| tokens[token].1))
+        .collect();
+
+    let nullable = accept[0].is_some();
+
+    let steps = minimize(&dfa, &accept).into_iter().map(|(edges, kind)| {
+        let mut ranges: Vec<(RangeInclusive<char>, usize)> = vec![];
+
+        for (symbol, next) in edges {
+            let (start, end) = intervals[symbol];
+
+            // Merge neighbouring intervals that lead to the same place.
+            if let Some((range, last)) = ranges.last_mut() {
+                if *last == next && *range.end() as u32 + 1 == start {
+                    *range = *range.start()..=char_before(end + 1);
+                    continue;
+                }
+            }
+
+            if char_after(start) <= char_before(end + 1) {
+                ranges.push((char_after(start)..=char_before(end + 1), next));
+            }
+        }
+
+        Step(ranges, kind)
+    }).collect();
+
+    return Tokens { steps, conflicts, nullable };
+}
+
+/// The kind of token the steps make of exactly the text, if any.
+pub fn lex(steps: &[Step<RangeInclusive<char>>], text: &str) -> Option<Kind> {
+    let mut step = 0;
+
+    for chr in text.chars() {
+        step = steps[step].rules().iter().find(|(range, _)| range.contains(&chr))?.1;
+    }
+
+    return steps[step].kind();
+}
+
+/// Find the states where tokens of different kinds end.
+fn conflicts(dfa: &Dfa, tokens: &[(Expr, Kind)], intervals: &[(u32, u32)]) -> Vec<Conflict> {
+    // Find the shortest text leading to every state, breadth first.
+    let mut examples: Vec<Option<String>> = vec![None; dfa.states.len()];
+    examples[0] = Some(String::new());
+
+    let mut todo = vec![0];
+    while !todo.is_empty() {
+        let mut next_todo = vec![];
+
+        for state in todo {
+            for &(symbol, next) in &dfa.states[state].edges {
+                if examples[next].is_none() {
+                    let mut example = examples[state].clone().unwrap();
+                    example.push(char_after(intervals[symbol].0));
+
+                    examples[next] = Some(example);
+                    next_todo.push(next);
+                }
+            }
+        }
+
+        todo = next_todo;
+    }
+
+    let mut conflicts: Vec<Conflict> = vec![];
+
+    for (state, example) in dfa.states.iter().zip(examples) {
+        for (i, &first) in state.accept.iter().enumerate() {
+            for &second in &state.accept[i + 1..] {
+                let known = conflicts.iter().any(|c| c.first == first && c.second == second);
+
+                if tokens[first].1 != tokens[second].1 && !known {
+                    conflicts.push(Conflict {
+                        first, second,
+                        example: example.clone().unwrap(),
+                    });
+                }
+            }
+        }
+    }
+
+    return conflicts;
+}
+
+/// A state of a minimized dfa: its edges by symbol, and the kind it accepts.
+type State = (Vec<(usize, usize)>, Option<Kind>);
+
+/// Merge equivalent states of the dfa, by repeatedly splitting groups of states that
+/// accept different kinds or lead to different groups. The start state stays first.
+fn minimize(dfa: &Dfa, accept: &[Option<Kind>]) -> Vec<State> {
+    let mut group: Vec<usize> = number(accept.iter().cloned());
+    let mut count = 0;
+
+    loop {
+        let signatures: Vec<_> = dfa.states.iter().enumerate().map(|(state, dfa_state)| (
+            group[state],
+            dfa_state.edges.iter().map(|&(symbol, next)| (symbol, group[next])).collect::<Vec<_>>(),
+        )).collect();
+
+        group = number(signatures.into_iter());
+
+        let new_count = group.iter().max().map_or(0, |max| max + 1);
+        if new_count == count {
+            break;
+        }
+
+        count = new_count;
+    }
+
+    let mut states = vec![None; count];
+    for (state, dfa_state) in dfa.states.iter().enumerate() {
+        if states[group[state]].is_none() {
+            let edges = dfa_state.edges.iter().map(|&(symbol, next)| (symbol, group[next])).collect();
+            states[group[state]] = Some((edges, accept[state]));
+        }
+    }
+
+    return states.into_iter().map(Option::unwrap).collect();
+}
+
+/// Give each distinct value a number, in order of first appearance.
+fn number<T: Eq + std::hash::Hash>(values: impl Iterator<Item = T>) -> Vec<usize> {
+    let mut ids = HashMap::new();
+
+    return values.map(|value| {
+        let id = ids.len();
+        *ids.entry(value).or_insert(id)
+    }).collect();
+}
+
+/// Call the function with every range in a token.
+fn ranges(expr: &Expr, func: &mut impl FnMut(char, char)) {
+    match expr {
+        Expr::Range(start, end) => func(*start, *end),
+        Expr::Seq(exprs) | Expr::Choice(exprs) => exprs.iter().for_each(|expr| ranges(expr, func)),
+        Expr::Optional(expr) | Expr::Many(expr) | Expr::Many1(expr) => ranges(expr, func),
+        _ => {},
+    }
+}
+
+/// Replace every literal in a token with the sequence of characters it is made of, and every
+/// class with the ranges in it.
+fn spell(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Text(text) => Expr::Seq(text.chars().map(|chr| Expr::Range(chr, chr)).collect()),
+        Expr::Class(class) => Expr::Choice(class.chars().ranges().iter().map(|(start, end)| Expr::Range(*start, *end)).collect()),
+        Expr::Seq(exprs) => Expr::Seq(exprs.iter().map(spell).collect()),
+        Expr::Choice(exprs) => Expr::Choice(exprs.iter().map(spell).collect()),
+        Expr::Optional(expr) => Expr::Optional(Box::new(spell(expr))),
+        Expr::Many(expr) => Expr::Many(Box::new(spell(expr))),
+        Expr::Many1(expr) => Expr::Many1(Box::new(spell(expr))),
+        _ => expr.clone(),
+    }
+}
+
+/// Every character not in the given ranges.
+pub fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort();
+
+    let mut result = vec![];
+    let mut next = 0;
+
+    for (start, end) in ranges {
+        if next < start as u32 && char_after(next) <= char_before(start as u32) {
+            result.push((char_after(next), char_before(start as u32)));
+        }
+
+        next = next.max(end as u32 + 1);
+    }
+
+    if next <= char::MAX as u32 {
+        result.push((char_after(next), char::MAX));
+    }
+
+    return result;
+}
+
+/// The first character at or after the code point, skipping over the surrogates.
+fn char_after(point: u32) -> char {
+    return std::char::from_u32(point).unwrap_or('\u{E000}');
+}
+
+/// The last character before the code point, skipping over the surrogates.
+fn char_before(point: u32) -> char {
+    return std::char::from_u32(point - 1).unwrap_or('\u{D7FF}');
+}
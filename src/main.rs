@@ -3,6 +3,7 @@
 // Publish the children modules
 pub mod document;
 pub mod rules;
+pub mod grammar;
 
 use crate::rules::*;
 use crate::document::*;
@@ -26,97 +27,118 @@ pub const BLUE: RGB = RGB(0, 110, 114);
 /// Orange
 pub const ORANGE: RGB = RGB(186, 107, 71);
 
-fn make_language() -> Vec<Box<dyn Rule>> {
+fn make_language() -> Language {
+    let mut language = LanguageBuilder::new();
+
+    let whitespace = language.kind("Whitespace");
+    let name = language.kind("Name");
+    let number = language.kind("Number");
+    let punctuation = language.kind("Punctuation");
+    let equal_expression = language.kind("EqualExpression");
+    let operator_expression = language.kind("OperatorExpression");
+
     let lexer = Lexer::new(vec![
         Step(vec![
             // Whitespace
-            (('\t'..=' '), 1),
+            (CharClass::Whitespace, 1),
 
             // Word
-            (('a'..='z'), 2),
-            (('A'..='Z'), 2),
-            (('_'..='_'), 2),
-            (('\''..='\''), 2),
+            (CharClass::XidStart, 2),
+            (CharClass::char('_'), 2),
+            (CharClass::char('\''), 2),
 
             // Punctuation
-            (('!'..='/'), 3),
-            ((':'..='@'), 3),
-            (('{'..='~'), 3),
+            (CharClass::Range('!', '/'), 3),
+            (CharClass::Range(':', '@'), 3),
+            (CharClass::Range('{', '~'), 3),
 
             // Number
-            (('0'..='9'), 4),
+            (CharClass::Range('0', '9'), 4),
         ], None),
 
         // Whitespace
         Step(vec![
-             (('\t'..=' '), 1)
-        ], Some(Kind::Whitespace)),
+             (CharClass::Whitespace, 1)
+        ], Some(whitespace)),
 
         // Word
         Step(vec![
-            (('a'..='z'), 2),
-            (('A'..='Z'), 2),
-            (('_'..='_'), 2),
-            (('0'..='9'), 2),
-            (('\''..='\''), 2),
-        ], Some(Kind::Name)),
+            (CharClass::XidContinue, 2),
+            (CharClass::char('\''), 2),
+        ], Some(name)),
 
         // Punctuation
         Step(vec![
-        ], Some(Kind::Punctuation)),
+        ], Some(punctuation)),
 
         // Number
         Step(vec![
-             (('0'..='9'), 4),
-             (('.'..='.'), 5)
-        ], Some(Kind::Number)),
+             (CharClass::Range('0', '9'), 4),
+             (CharClass::char('.'), 5)
+        ], Some(number)),
         Step(vec![
-             (('0'..='9'), 5)
-        ], Some(Kind::Number)),
+             (CharClass::Range('0', '9'), 5)
+        ], Some(number)),
     ]);
 
     let file = Automata::new(vec![
         Step(vec![
-             ((2, Kind::EqualExpression), 0),
-             ((2, Kind::Error), 0)
-        ], Some(Kind::File))
+             (Edge::new("statement", equal_expression), 0),
+             (Edge::new("statement", operator_expression), 0),
+             (Edge::new("lexer", whitespace), 0),
+        ], Some(Kind::FILE))
     ]);
 
-    let assign = Automata::new(vec![
+    // A broken statement is recovered from, up until the name that starts the next one.
+    let statement = Automata::recovering(vec![
         Step(vec![
-             ((1, Kind::Name), 1),
-        ], Some(Kind::Error)),
+             (Edge::new("lexer", name), 1),
+        ], None),
         Step(vec![
-             ((1, Kind::Punctuation), 2),
-        ], Some(Kind::Error)),
+             (Edge::new("lexer", whitespace), 1),
+             (Edge::text("lexer", punctuation, "="), 2),
+             (Edge::texts("lexer", punctuation, &["+", "-", "*", "/"]), 4),
+        ], None),
+
+        // x = 1
         Step(vec![
-             ((1, Kind::Number), 3),
-        ], Some(Kind::Error)),
+             (Edge::new("lexer", whitespace), 2),
+             (Edge::new("lexer", number), 3),
+        ], None),
         Step(vec![
-        ], Some(Kind::EqualExpression)),
+        ], Some(equal_expression)),
+
+        // x + 1
+        Step(vec![
+             (Edge::new("lexer", whitespace), 4),
+             (Edge::new("lexer", number), 5),
+        ], None),
+        Step(vec![
+        ], Some(operator_expression)),
+    ], vec![
+        Edge::new("lexer", name),
     ]);
 
-    return vec![
-        file,        // 0
-        lexer,       // 1
+    language.rule("file", file);
+    language.rule("lexer", lexer);
+
+    // Expressions
+    language.rule("statement", statement);
 
-        // Expressions
-        assign,      // 2
-    ];
+    return language.build().expect("the demo language should be valid");
 }
 
 fn color(doc: &Document, index: usize) -> Option<RGB> {
     doc.get_filter(index, |node| {
-        match node.kind {
-            Kind::EqualExpression |
-            Kind::File => None,
+        match doc.lang.kinds.name(node.kind) {
+            "Whitespace"  => Some(WHITE),
+            "Name"        => Some(WHITE),
+            "Number"      => Some(BLUE),
+            "Punctuation" => Some(ORANGE),
 
-            Kind::Whitespace  => Some(WHITE),
-            Kind::Name        => Some(WHITE),
-            Kind::Number      => Some(BLUE),
-            Kind::Punctuation => Some(ORANGE),
+            "Error" => Some(ORANGE),
 
-            Kind::Error => Some(ORANGE),
+            _ => None,
         }
     })
 }
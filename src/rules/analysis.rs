@@ -0,0 +1,288 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::document::Kind;
+
+use super::Rule;
+
+/// A set of characters, stored as sorted ranges that don't touch.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CharSet(Vec<(char, char)>);
+
+impl CharSet {
+    /// The set with no characters.
+    pub fn empty() -> CharSet {
+        return CharSet(vec![]);
+    }
+
+    /// The set with every character.
+    pub fn all() -> CharSet {
+        return CharSet(vec![('\0', char::MAX)]);
+    }
+
+    /// The set of characters in the ranges.
+    pub fn from_ranges(ranges: &[(char, char)]) -> CharSet {
+        let mut set = CharSet(ranges.iter().filter(|(start, end)| start <= end).cloned().collect());
+        set.merge();
+
+        return set;
+    }
+
+    /// Every character not in the set.
+    pub fn complement(&self) -> CharSet {
+        let mut ranges = vec![];
+        let mut next = 0;
+
+        for (start, end) in &self.0 {
+            if next < *start as u32 {
+                ranges.push((char_at(next, true), char_at(*start as u32 - 1, false)));
+            }
+
+            next = *end as u32 + 1;
+        }
+
+        if next <= char::MAX as u32 {
+            ranges.push((char_at(next, true), char::MAX));
+        }
+
+        // Ranges made only of surrogates have nothing in them.
+        ranges.retain(|(start, end)| start <= end);
+
+        return CharSet(ranges);
+    }
+
+    /// The ranges in the set, in order.
+    pub fn ranges(&self) -> &[(char, char)] {
+        return &self.0;
+    }
+
+    /// Is the character in the set?
+    pub fn contains(&self, chr: char) -> bool {
+        return self.0.binary_search_by(|(start, end)| {
+            if *end < chr {
+                std::cmp::Ordering::Less
+            } else if *start > chr {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        }).is_ok();
+    }
+
+    /// Is every character of the other set in this one?
+    pub fn covers(&self, other: &CharSet) -> bool {
+        return other.0.iter().all(|(start, end)| {
+            let index = self.0.partition_point(|(_, b)| b < start);
+            self.0.get(index).map_or(false, |(a, b)| a <= start && end <= b)
+        });
+    }
+
+    /// Do the sets have a character in common?
+    pub fn intersects(&self, other: &CharSet) -> bool {
+        return other.0.iter().any(|(start, end)| {
+            let index = self.0.partition_point(|(_, b)| b < start);
+            self.0.get(index).map_or(false, |(a, _)| a <= end)
+        });
+    }
+
+    /// Add a range of characters to the set.
+    pub fn insert(&mut self, range: RangeInclusive<char>) {
+        if range.start() <= range.end() {
+            self.0.push((*range.start(), *range.end()));
+            self.merge();
+        }
+    }
+
+    /// Add every character of the other set. Returns true if anything was added.
+    pub fn union(&mut self, other: &CharSet) -> bool {
+        if self.covers(other) {
+            return false;
+        }
+
+        self.0.extend_from_slice(&other.0);
+        self.merge();
+
+        return true;
+    }
+
+    /// Sort the ranges, and merge the ones that overlap or touch.
+    fn merge(&mut self) {
+        self.0.sort();
+
+        let mut merged: Vec<(char, char)> = vec![];
+        for (start, end) in self.0.drain(..) {
+            if let Some(last) = merged.last_mut() {
+                if start as u32 <= last.1 as u32 + 1 {
+                    last.1 = last.1.max(end);
+                    continue;
+                }
+            }
+
+            merged.push((start, end));
+        }
+
+        self.0 = merged;
+    }
+}
+
+/// The character at the code point, or the nearest one past the surrogates going forward or back.
+fn char_at(point: u32, forward: bool) -> char {
+    return std::char::from_u32(point).unwrap_or(if forward { '\u{E000}' } else { '\u{D7FF}' });
+}
+
+/// How a rule can start.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct First {
+    /// Can the rule match without consuming anything?
+    pub nullable: bool,
+
+    /// The characters the rule can start with when it does consume something.
+    pub chars: CharSet,
+}
+
+impl First {
+    /// Nothing is known about how the rule starts, so it could start any way.
+    pub fn unknown() -> First {
+        return First {
+            nullable: true,
+            chars: CharSet::all(),
+        };
+    }
+
+    /// Is the rule known to match without consuming anything? A rule that doesn't say how it
+    /// starts might, but isn't counted.
+    pub fn known_nullable(&self) -> bool {
+        return self.nullable && *self != First::unknown();
+    }
+
+    /// Could the rule match here, given the next character?
+    pub fn can_start(&self, next: Option<char>) -> bool {
+        return self.nullable || next.map_or(false, |chr| self.chars.contains(chr));
+    }
+}
+
+/// A likely mistake in a language, that doesn't stop it from being built.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Warning {
+    /// The rule can't be reached from the file rule, so it's never used.
+    UnreachableRule(String),
+
+    /// An edge can never be followed, because an earlier edge of the step always wins.
+    ShadowedEdge {
+        /// The rule with the edge.
+        rule: String,
+
+        /// The step the edge leaves from.
+        step: usize,
+
+        /// The position of the edge in the step.
+        edge: usize,
+    },
+
+    /// Two edges of a step can start with the same character, so the earlier one wins even
+    /// if the later one would have gone further.
+    Ambiguous {
+        /// The rule with the edges.
+        rule: String,
+
+        /// The step the edges leave from.
+        step: usize,
+
+        /// The position of the first edge in the step.
+        first: usize,
+
+        /// The position of the second edge in the step.
+        second: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::UnreachableRule(rule) =>
+                write!(f, "the rule `{}` is never used by the file rule", rule),
+            Warning::ShadowedEdge { rule, step, edge } =>
+                write!(f, "edge {} of step {} of `{}` is never followed", edge, step, rule),
+            Warning::Ambiguous { rule, step, first, second } =>
+                write!(f, "edges {} and {} of step {} of `{}` can start the same way", first, second, step, rule),
+        }
+    }
+}
+
+/// What can be worked out about the rules of a language without parsing anything.
+pub struct Analysis {
+    /// How each rule can start, by rule index.
+    pub first: Vec<First>,
+
+    /// Can each rule be reached from the file rule?
+    pub reachable: Vec<bool>,
+
+    /// The likely mistakes found.
+    pub warnings: Vec<Warning>,
+}
+
+impl Analysis {
+    /// Analyse the rules. The names and the rules each one refers to are by rule index.
+    pub fn new(rules: &[Box<dyn Rule>], names: &[String], uses: &[Vec<usize>]) -> Analysis {
+        let mut analysis = Analysis {
+            first: vec![First::default(); rules.len()],
+            reachable: vec![false; rules.len()],
+            warnings: vec![],
+        };
+
+        // The sets only ever grow, so keep recomputing them until nothing changes.
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for (index, rule) in rules.iter().enumerate() {
+                let first = rule.first(rules, &analysis);
+
+                let old = &mut analysis.first[index];
+                if first.nullable && !old.nullable {
+                    old.nullable = true;
+                    changed = true;
+                }
+
+                changed |= old.chars.union(&first.chars);
+            }
+        }
+
+        // Find every rule that can be reached from the file rule.
+        let mut todo = vec![0];
+        while let Some(index) = todo.pop() {
+            if index < rules.len() && !analysis.reachable[index] {
+                analysis.reachable[index] = true;
+                todo.extend(&uses[index]);
+            }
+        }
+
+        for (index, name) in names.iter().enumerate() {
+            if !analysis.reachable[index] {
+                analysis.warnings.push(Warning::UnreachableRule(name.clone()));
+            }
+        }
+
+        for (rule, name) in rules.iter().zip(names) {
+            let warnings = rule.warnings(name, rules, &analysis);
+            analysis.warnings.extend(warnings);
+        }
+
+        return analysis;
+    }
+
+    /// Can the rule match without consuming anything?
+    pub fn nullable(&self, rule: usize) -> bool {
+        return self.first[rule].nullable;
+    }
+
+    /// How the rule can start when it creates a node of the given kind.
+    pub fn first_kind(&self, rules: &[Box<dyn Rule>], rule: usize, kind: Kind) -> First {
+        return rules[rule].first_kind(kind).unwrap_or_else(|| self.first[rule].clone());
+    }
+
+    /// The characters the rule can start with.
+    pub fn chars(&self, rule: usize) -> &CharSet {
+        return &self.first[rule].chars;
+    }
+}
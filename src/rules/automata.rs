@@ -1,6 +1,7 @@
+use std::collections::VecDeque;
 use std::rc::Rc;
-use crate::document::{Kind, Node, Parser};
-use super::Rule;
+use crate::document::{Checkpoint, Expected, Kind, Node, Parser};
+use super::{Analysis, CharSet, First, LanguageError, Rule, RuleRef, Warning};
 
 /// A step in the automata.
 pub struct Step<T>(pub Vec<(T, usize)>, pub Option<Kind>);
@@ -17,41 +18,664 @@ impl<T> Step<T> {
     }
 }
 
+/// The kinds of node the steps can end with, each once.
+pub fn step_kinds<T>(steps: &[Step<T>]) -> Vec<Kind> {
+    let mut kinds = vec![];
+
+    for kind in steps.iter().filter_map(|step| step.kind()) {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+
+    return kinds;
+}
+
+/// Check that every edge goes to a step that exists, and that the steps that end the rule can
+/// be reached from the starting steps.
+pub fn validate_steps<T>(name: &str, steps: &[Step<T>], starts: &[usize]) -> Vec<LanguageError> {
+    let mut errors = vec![];
+
+    if steps.is_empty() {
+        return vec![LanguageError::NoSteps(name.to_string())];
+    }
+
+    for (step, Step(edges, _)) in steps.iter().enumerate() {
+        for (_, target) in edges {
+            if *target >= steps.len() {
+                errors.push(LanguageError::MissingStep {
+                    rule: name.to_string(),
+                    step, target: *target,
+                });
+            }
+        }
+    }
+
+    // Find every step that can be reached from the starting ones.
+    let mut reached = vec![false; steps.len()];
+    let mut todo: Vec<usize> = starts.iter().copied().filter(|start| *start < steps.len()).collect();
+    for start in &todo {
+        reached[*start] = true;
+    }
+
+    while let Some(step) = todo.pop() {
+        for (_, target) in steps[step].rules() {
+            if *target < steps.len() && !reached[*target] {
+                reached[*target] = true;
+                todo.push(*target);
+            }
+        }
+    }
+
+    for (step, Step(_, kind)) in steps.iter().enumerate() {
+        if kind.is_some() && !reached[step] {
+            errors.push(LanguageError::UnreachableStep { rule: name.to_string(), step });
+        }
+    }
+
+    if !steps.iter().zip(&reached).any(|(step, reached)| *reached && step.kind().is_some()) {
+        errors.push(LanguageError::NeverAccepts(name.to_string()));
+    }
+
+    return errors;
+}
+
+/// An edge of an automata, to the node of another rule.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Edge {
+    /// The rule that creates the node.
+    pub rule: RuleRef,
+
+    /// The kind the node has to be.
+    pub kind: Kind,
+
+    /// The texts the node can have. If there are none, any text will do.
+    pub texts: Vec<String>,
+
+    /// The texts the node can't have, like the keywords a name can't be.
+    pub except: Vec<String>,
+}
+
+impl Edge {
+    /// An edge to any node of the kind.
+    pub fn new(rule: &str, kind: Kind) -> Edge {
+        return Edge { rule: RuleRef::new(rule), kind, texts: vec![], except: vec![] };
+    }
+
+    /// An edge to a node of the kind with exactly the text, like the keyword `let`.
+    pub fn text(rule: &str, kind: Kind, text: &str) -> Edge {
+        return Edge::texts(rule, kind, &[text]);
+    }
+
+    /// An edge to a node of the kind with any of the texts, like `+` or `-`.
+    pub fn texts(rule: &str, kind: Kind, texts: &[&str]) -> Edge {
+        return Edge {
+            rule: RuleRef::new(rule),
+            kind,
+            texts: texts.iter().map(|text| text.to_string()).collect(),
+            except: vec![],
+        };
+    }
+
+    /// An edge to a node of the kind with any text but the given ones, like a name that isn't
+    /// the keyword `let`.
+    pub fn except(rule: &str, kind: Kind, texts: &[&str]) -> Edge {
+        return Edge {
+            rule: RuleRef::new(rule),
+            kind,
+            texts: vec![],
+            except: texts.iter().map(|text| text.to_string()).collect(),
+        };
+    }
+
+    /// Does the edge allow nodes of its kind with any text?
+    pub fn any_text(&self) -> bool {
+        return self.texts.is_empty() && self.except.is_empty();
+    }
+
+    /// Can the node have the text?
+    pub fn allows(&self, text: &str) -> bool {
+        return (self.texts.is_empty() || self.texts.iter().any(|allowed| allowed == text)) &&
+            !self.except.iter().any(|excluded| excluded == text);
+    }
+
+    /// What the edge is looking for, for diagnostics.
+    pub fn expected(&self) -> Vec<Expected> {
+        if self.texts.is_empty() {
+            return vec![Expected::Kind(self.kind)];
+        }
+
+        return self.texts.iter().map(|text| Expected::Text(text.clone())).collect();
+    }
+
+    /// Does this edge follow every node the other one does?
+    fn covers(&self, other: &Edge) -> bool {
+        let texts = if other.texts.is_empty() {
+            self.texts.is_empty() && self.except.iter().all(|text| other.except.contains(text))
+        } else {
+            other.texts.iter().all(|text| self.allows(text))
+        };
+
+        return self.rule == other.rule && self.kind == other.kind && texts;
+    }
+
+    /// How the nodes this edge follows can start.
+    fn first(&self, rules: &[Box<dyn Rule>], analysis: &Analysis) -> First {
+        let mut first = analysis.first_kind(rules, self.rule.index(), self.kind);
+
+        // With a fixed text, only its first character can start the node.
+        if !self.texts.is_empty() {
+            let mut chars = CharSet::empty();
+
+            for chr in self.texts.iter().filter_map(|text| text.chars().next()) {
+                chars.insert(chr..=chr);
+            }
+
+            first.nullable &= self.texts.iter().any(|text| text.is_empty());
+            first.chars = chars;
+        }
+
+        return first;
+    }
+}
+
+impl From<(&str, Kind)> for Edge {
+    fn from((rule, kind): (&str, Kind)) -> Edge {
+        return Edge::new(rule, kind);
+    }
+}
+
 /// A recusice definite finite automata rule.
 pub struct Automata {
     /// The Steps in the dfa.
-    steps: Vec<Step<(usize, Kind)>>,
+    steps: Vec<Step<Edge>>,
+
+    /// Should dead ends back out and try the remaining edges?
+    ordered: bool,
+
+    /// The nodes to recover at, if the automata gets stuck. There is no recovery without them.
+    sync: Vec<Edge>,
 }
 
 impl Automata {
-    /// Constructor for the automata.
-    pub fn new(steps: Vec<Step<(usize, Kind)>>) -> Box<dyn Rule> {
-        return Box::new(Automata { steps });
+    /// Constructor for the automata. Edges refer to other rules by name, as a `(rule, kind)`
+    /// pair or an Edge.
+    pub fn new<T: Into<Edge>>(steps: Vec<Step<T>>) -> Box<dyn Rule> {
+        return Box::new(Automata::build(steps, false));
+    }
+
+    /// An automata with ordered choice. The edges of a step are tried in order, and if a
+    /// path reaches a step that can't end the node, the automata backs out of it and tries
+    /// the next edge instead.
+    pub fn ordered<T: Into<Edge>>(steps: Vec<Step<T>>) -> Box<dyn Rule> {
+        return Box::new(Automata::build(steps, true));
+    }
+
+    /// An automata that recovers from errors once it has started a node.
+    ///
+    /// When it gets stuck at a step that can't end the node, it fills in a single missing
+    /// node if the one after it is there, or else skips the text into an error node until it
+    /// can carry on. At one of the sync nodes, or the end of the text, it stops skipping and
+    /// fills in the missing nodes to the nearest end instead, leaving the sync node for
+    /// whatever comes next.
+    pub fn recovering<T: Into<Edge>, S: Into<Edge>>(steps: Vec<Step<T>>, sync: Vec<S>) -> Box<dyn Rule> {
+        let mut automata = Automata::build(steps, false);
+        automata.sync = sync.into_iter().map(|edge| edge.into()).collect();
+
+        return Box::new(automata);
+    }
+
+    fn build<T: Into<Edge>>(steps: Vec<Step<T>>, ordered: bool) -> Automata {
+        let steps = steps.into_iter().map(|Step(edges, kind)| Step(
+            edges.into_iter().map(|(edge, i)| (edge.into(), i)).collect(),
+            kind,
+        )).collect();
+
+        return Automata { steps, ordered, sync: vec![] };
+    }
+}
+
+impl Automata {
+    /// Try to follow an edge, adding the node it creates to subs. Predicates create no node.
+    /// If it can't be followed, the parser is told what was expected.
+    fn follow<'a>(
+        parser: &mut Parser<'a, '_>,
+        edge: &Edge,
+        subs: &mut Vec<Rc<Node<'a>>>,
+    ) -> bool {
+        if Automata::try_follow(parser, edge, subs) {
+            return true;
+        }
+
+        if !parser.is_predicate(edge.rule.index()) {
+            parser.expect(edge.expected());
+        }
+
+        return false;
+    }
+
+    /// Try to follow an edge, without telling the parser what was expected if it can't be.
+    fn try_follow<'a>(
+        parser: &mut Parser<'a, '_>,
+        edge: &Edge,
+        subs: &mut Vec<Rc<Node<'a>>>,
+    ) -> bool {
+        let rule = edge.rule.index();
+
+        if parser.is_predicate(rule) {
+            return parser.test(rule);
+        }
+
+        // Don't bother with rules that can't start with the next character.
+        if !parser.can_start(rule) {
+            return false;
+        }
+
+        let start = parser.save();
+
+        if let Some(node) = parser.parse_kind(rule, edge.kind) {
+            if edge.any_text() || edge.allows(&parser.text(&node)) {
+                subs.push(node);
+                return true;
+            }
+
+            parser.restore(start);
+        }
+
+        return false;
+    }
+
+    /// Follow the first edge of the step that can be, and return the step it goes to.
+    fn next<'a>(
+        &self,
+        parser: &mut Parser<'a, '_>,
+        step: usize,
+        subs: &mut Vec<Rc<Node<'a>>>,
+        seen: &mut (Checkpoint, Vec<usize>),
+    ) -> Option<usize> {
+        for (edge, next) in self.steps[step].rules() {
+            let start = parser.save();
+            let count = subs.len();
+
+            if !Automata::follow(parser, edge, subs) {
+                continue;
+            }
+
+            if Automata::reach(parser, *next, seen) {
+                return Some(*next);
+            }
+
+            parser.restore(start);
+            subs.truncate(count);
+        }
+
+        return None;
+    }
+
+    /// Note that the step was reached, unless it was already reached without moving since.
+    fn reach(parser: &Parser, step: usize, seen: &mut (Checkpoint, Vec<usize>)) -> bool {
+        let here = parser.save();
+
+        if here != seen.0 {
+            *seen = (here, vec![]);
+        }
+
+        if seen.1.contains(&step) {
+            return false;
+        }
+
+        seen.1.push(step);
+
+        return true;
+    }
+
+    /// Could the edge be followed here? Nothing is consumed.
+    fn can_follow(parser: &mut Parser, edge: &Edge) -> bool {
+        let start = parser.save();
+        let followed = Automata::try_follow(parser, edge, &mut vec![]);
+        parser.restore(start);
+
+        return followed;
+    }
+
+    /// Get unstuck at a step that can't end the node, and return the step to carry on from.
+    fn recover<'a>(
+        &self,
+        parser: &mut Parser<'a, '_>,
+        step: usize,
+        subs: &mut Vec<Rc<Node<'a>>>,
+    ) -> Option<usize> {
+        // Give up on the rest of the node, and fill in what's missing to the nearest end.
+        if parser.at_end() || self.at_sync(parser) {
+            let (edge, next) = self.to_end(parser, step)?;
+            subs.push(parser.missing(edge.rule.index(), edge.kind, edge.expected()));
+
+            return Some(next);
+        }
+
+        // A single node might be missing, if the one after it is here.
+        for (edge, next) in self.steps[step].rules() {
+            if parser.is_predicate(edge.rule.index()) || *next >= self.steps.len() {
+                continue;
+            }
+
+            let missing = parser.missing(edge.rule.index(), edge.kind, edge.expected());
+            let start = parser.save();
+
+            for (after, last) in self.steps[*next].rules() {
+                let mut found = vec![];
+
+                if !parser.is_predicate(after.rule.index()) && Automata::try_follow(parser, after, &mut found) {
+                    if parser.offset > start.offset() {
+                        subs.push(missing);
+                        subs.extend(found);
+
+                        return Some(*last);
+                    }
+
+                    parser.restore(start.clone());
+                }
+            }
+        }
+
+        // Otherwise skip text until something can follow, a sync node is reached, or the text ends.
+        // A sync node is only one if its edge would take it, kind and text, just like the
+        // edges of the step.
+        let start = parser.save();
+
+        while parser.next_if(|_| true) {
+            let edges = self.steps[step].rules();
+            if parser.at_end() || self.at_sync(parser) || edges.iter().any(|(edge, _)| Automata::can_follow(parser, edge)) {
+                break;
+            }
+        }
+
+        if parser.offset == start.offset() {
+            return None;
+        }
+
+        subs.push(parser.error(&start, vec![]));
+
+        return Some(step);
+    }
+
+    /// Is one of the sync nodes here?
+    fn at_sync(&self, parser: &mut Parser) -> bool {
+        return self.sync.iter().any(|edge| Automata::can_follow(parser, edge));
+    }
+
+    /// The first edge on the shortest way from the step to one that can end the node.
+    /// Predicates can't be filled in, so they aren't followed.
+    fn to_end(&self, parser: &Parser, step: usize) -> Option<(&Edge, usize)> {
+        let mut first: Vec<Option<(&Edge, usize)>> = vec![None; self.steps.len()];
+        let mut todo = VecDeque::from(vec![step]);
+        let mut seen = vec![false; self.steps.len()];
+        seen[step] = true;
+
+        while let Some(current) = todo.pop_front() {
+            if self.steps[current].kind().is_some() {
+                return first[current];
+            }
+
+            for (edge, next) in self.steps[current].rules() {
+                if *next < self.steps.len() && !seen[*next] && !parser.is_predicate(edge.rule.index()) {
+                    seen[*next] = true;
+                    first[*next] = first[current].or(Some((edge, *next)));
+                    todo.push_back(*next);
+                }
+            }
+        }
+
+        return None;
+    }
+
+    /// Walk the steps depth first, backing out of paths that dead end.
+    fn parse_ordered<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let mut subs: Vec<Rc<Node<'a>>> = vec![];
+
+        // The steps taken so far, the next edge to try from each, and where the parser was and
+        // the number of children when we got there.
+        let mut path = vec![(0, 0, parser.save(), 0)];
+
+        while let Some((step, edge, _, _)) = path.last_mut() {
+            let step = *step;
+
+            if let Some((rule, next)) = self.steps[step].rules().get(*edge) {
+                *edge += 1;
+
+                if Automata::follow(parser, rule, &mut subs) {
+                    // Coming back to a step on the path without moving would never end.
+                    let here = parser.save();
+                    let circle = path.iter().any(|(other, _, checkpoint, _)| other == next && *checkpoint == here);
+
+                    if !circle {
+                        path.push((*next, 0, here, subs.len()));
+                    } else if let Some((_, _, checkpoint, count)) = path.last() {
+                        parser.restore(checkpoint.clone());
+                        subs.truncate(*count);
+                    }
+                }
+
+                continue;
+            }
+
+            // Every edge was tried, so stop here if we can.
+            if let Some(kind) = self.steps[step].kind() {
+                return Some((kind, subs));
+            }
+
+            // Otherwise back out of the step.
+            path.pop();
+            if let Some((_, _, checkpoint, count)) = path.last() {
+                parser.restore(checkpoint.clone());
+                subs.truncate(*count);
+            }
+        }
+
+        return None;
     }
 }
 
 impl Rule for Automata {
     fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        if self.ordered {
+            return self.parse_ordered(parser);
+        }
+
         let mut subs = vec![];
         let mut step = 0;
 
-        while self.steps[step].rules().iter().any(|((rule, kind), i)| {
-            if let Some(node) = parser.parse(*rule) {
-                subs.push(node);
+        // The steps reached since the parser last moved. Going back to one of them would go
+        // round in circles forever.
+        let mut seen = (parser.save(), vec![0]);
 
-                step = *i;
+        loop {
+            while let Some(next) = self.next(parser, step, &mut subs, &mut seen) {
+                step = next;
+            }
 
-                true
-            } else {
-                false
+            if let Some(kind) = self.steps[step].kind() {
+                return Some((kind, subs));
             }
-        }) {}
 
-        if let Some(kind) = self.steps[step].kind() {
-            return Some((kind, subs));
-        } else {
-            return None;
+            // Only recover once the node has started, so the rule can still fail outright.
+            if self.sync.is_empty() || subs.is_empty() {
+                return None;
+            }
+
+            step = self.recover(parser, step, &mut subs)?;
+
+            if !Automata::reach(parser, step, &mut seen) {
+                return None;
+            }
         }
     }
-}
 
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        return self.steps.iter_mut()
+            .flat_map(|step| step.0.iter_mut())
+            .map(|(edge, _)| &mut edge.rule)
+            .chain(self.sync.iter_mut().map(|edge| &mut edge.rule))
+            .collect();
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        return step_kinds(&self.steps);
+    }
+
+    fn validate(&self, name: &str, rules: &[Box<dyn Rule>], analysis: &Analysis) -> Vec<LanguageError> {
+        let mut errors = validate_steps(name, &self.steps, &[0]);
+
+        // A loop made only of edges that can match nothing, like predicates or optional rules,
+        // could go round forever. Look for one with a depth first search, following only those
+        // edges.
+        // Each step is unvisited (0), on the current path (1), or done (2).
+        let mut state = vec![0u8; self.steps.len()];
+
+        for start in 0..self.steps.len() {
+            if state[start] != 0 {
+                continue;
+            }
+
+            let mut stack = vec![(start, 0)];
+
+            while let Some((step, edge)) = stack.last_mut() {
+                let step = *step;
+                state[step] = 1;
+
+                if let Some((next, target)) = self.steps[step].rules().get(*edge) {
+                    *edge += 1;
+
+                    if *target >= self.steps.len() || !next.first(rules, analysis).known_nullable() {
+                        continue;
+                    }
+
+                    match state[*target] {
+                        0 => stack.push((*target, 0)),
+                        1 => {
+                            errors.push(LanguageError::EmptyLoop { rule: name.to_string(), step: *target });
+                            return errors;
+                        },
+                        _ => {},
+                    }
+
+                    continue;
+                }
+
+                state[step] = 2;
+                stack.pop();
+            }
+        }
+
+        return errors;
+    }
+
+    fn sole_rules(&self, rules: &[Box<dyn Rule>], analysis: &Analysis) -> Vec<usize> {
+        let empty = |(edge, target): &(Edge, usize)| {
+            *target < self.steps.len() && edge.first(rules, analysis).known_nullable()
+        };
+
+        // The steps that can be reached from the start without consuming anything.
+        let mut from_start = vec![false; self.steps.len()];
+        let mut todo = vec![0];
+
+        while let Some(step) = todo.pop() {
+            if step < self.steps.len() && !from_start[step] {
+                from_start[step] = true;
+                todo.extend(self.steps[step].rules().iter().filter(|edge| empty(edge)).map(|(_, target)| *target));
+            }
+        }
+
+        // The steps that can end the node without consuming anything more.
+        let mut to_end: Vec<bool> = self.steps.iter().map(|step| step.kind().is_some()).collect();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (step, Step(edges, _)) in self.steps.iter().enumerate() {
+                if !to_end[step] && edges.iter().any(|edge| empty(edge) && to_end[edge.1]) {
+                    to_end[step] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut sole = vec![];
+
+        for (Step(edges, _), _) in self.steps.iter().zip(&from_start).filter(|(_, reached)| **reached) {
+            for (edge, target) in edges {
+                let index = edge.rule.index();
+
+                if *target < self.steps.len() && to_end[*target] && !rules[index].predicate() && !sole.contains(&index) {
+                    sole.push(index);
+                }
+            }
+        }
+
+        return sole;
+    }
+
+    fn first(&self, rules: &[Box<dyn Rule>], analysis: &Analysis) -> First {
+        let mut first = First::default();
+
+        // Follow the edges that can be empty, collecting what every edge along the way starts with.
+        let mut seen = vec![false; self.steps.len()];
+        let mut todo = vec![0];
+
+        while let Some(step) = todo.pop() {
+            if step >= self.steps.len() || seen[step] {
+                continue;
+            }
+
+            seen[step] = true;
+            first.nullable |= self.steps[step].kind().is_some();
+
+            for (edge, next) in self.steps[step].rules() {
+                let edge = edge.first(rules, analysis);
+                first.chars.union(&edge.chars);
+
+                if edge.nullable {
+                    todo.push(*next);
+                }
+            }
+        }
+
+        return first;
+    }
+
+    fn warnings(&self, name: &str, rules: &[Box<dyn Rule>], analysis: &Analysis) -> Vec<Warning> {
+        let mut warnings = vec![];
+
+        // An ordered automata backs out and tries the later edges, so they all get a chance.
+        if self.ordered {
+            return warnings;
+        }
+
+        for (step, Step(edges, _)) in self.steps.iter().enumerate() {
+            for (index, (edge, _)) in edges.iter().enumerate() {
+                let earlier = &edges[..index];
+
+                // The same rule gives the same node at the same place, so only the first edge
+                // that allows its text is used.
+                if earlier.iter().any(|(other, _)| other.covers(edge)) {
+                    warnings.push(Warning::ShadowedEdge { rule: name.to_string(), step, edge: index });
+                    continue;
+                }
+
+                let chars = edge.first(rules, analysis).chars;
+
+                let first = earlier.iter().position(|(other, _)| {
+                    other.rule.index() != edge.rule.index() &&
+                        other.first(rules, analysis).chars.intersects(&chars)
+                });
+
+                if let Some(first) = first {
+                    warnings.push(Warning::Ambiguous { rule: name.to_string(), step, first, second: index });
+                }
+            }
+        }
+
+        return warnings;
+    }
+}
@@ -0,0 +1,119 @@
+use std::ops::RangeInclusive;
+
+use super::tables;
+use super::CharSet;
+
+/// The characters a Lexer or Symbol edge accepts.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CharClass {
+    /// The characters in an inclusive range.
+    Range(char, char),
+
+    /// Characters that can start an identifier, the Unicode property XID_Start.
+    XidStart,
+
+    /// Characters that can continue an identifier, the Unicode property XID_Continue.
+    XidContinue,
+
+    /// Whitespace, the Unicode property White_Space.
+    Whitespace,
+
+    /// Letters of any script.
+    Letter,
+
+    /// Decimal digits of any script.
+    Digit,
+
+    /// The characters in any of the classes.
+    Union(Vec<CharClass>),
+
+    /// Any character not in the class.
+    Not(Box<CharClass>),
+
+    /// The end of the text. Following this edge doesn't consume anything.
+    End,
+}
+
+impl CharClass {
+    /// A class with just the one character.
+    pub fn char(chr: char) -> CharClass {
+        return CharClass::Range(chr, chr);
+    }
+
+    /// Any character not in the class.
+    pub fn negate(class: CharClass) -> CharClass {
+        return CharClass::Not(Box::new(class));
+    }
+
+    /// Does the class accept the character?
+    pub fn contains(&self, chr: char) -> bool {
+        return match self {
+            CharClass::Range(start, end) => *start <= chr && chr <= *end,
+            CharClass::XidStart => search(tables::XID_START, chr),
+            CharClass::XidContinue => search(tables::XID_CONTINUE, chr),
+            CharClass::Whitespace => search(tables::WHITE_SPACE, chr),
+            CharClass::Letter => search(tables::LETTER, chr),
+            CharClass::Digit => search(tables::DIGIT, chr),
+            CharClass::Union(classes) => classes.iter().any(|class| class.contains(chr)),
+            CharClass::Not(class) => !class.contains(chr),
+            CharClass::End => false,
+        };
+    }
+
+    /// Does the class accept the end of the text?
+    pub fn is_end(&self) -> bool {
+        return match self {
+            CharClass::End => true,
+            CharClass::Union(classes) => classes.iter().any(|class| class.is_end()),
+            _ => false,
+        };
+    }
+
+    /// Every character the class accepts.
+    pub fn chars(&self) -> CharSet {
+        return match self {
+            CharClass::Range(start, end) => CharSet::from_ranges(&[(*start, *end)]),
+            CharClass::XidStart => CharSet::from_ranges(tables::XID_START),
+            CharClass::XidContinue => CharSet::from_ranges(tables::XID_CONTINUE),
+            CharClass::Whitespace => CharSet::from_ranges(tables::WHITE_SPACE),
+            CharClass::Letter => CharSet::from_ranges(tables::LETTER),
+            CharClass::Digit => CharSet::from_ranges(tables::DIGIT),
+            CharClass::Union(classes) => {
+                let mut chars = CharSet::empty();
+
+                for class in classes {
+                    chars.union(&class.chars());
+                }
+
+                chars
+            },
+            CharClass::Not(class) => class.chars().complement(),
+            CharClass::End => CharSet::empty(),
+        };
+    }
+}
+
+impl From<RangeInclusive<char>> for CharClass {
+    fn from(range: RangeInclusive<char>) -> CharClass {
+        return CharClass::Range(*range.start(), *range.end());
+    }
+}
+
+impl From<char> for CharClass {
+    fn from(chr: char) -> CharClass {
+        return CharClass::char(chr);
+    }
+}
+
+/// Is the character in the sorted table of ranges?
+fn search(table: &[(char, char)], chr: char) -> bool {
+    return table.binary_search_by(|(start, end)| {
+        if *end < chr {
+            std::cmp::Ordering::Less
+        } else if *start > chr {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }).is_ok();
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::document::{Kind, Kinds};
+use super::{Analysis, Rule};
+
+/// A reference to another rule in the language by name, resolved when the language is built.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RuleRef {
+    /// The name of the rule.
+    name: String,
+
+    /// The position of the rule in the language, once resolved.
+    index: Option<usize>,
+}
+
+impl RuleRef {
+    /// Initializes an unresolved reference to the named rule.
+    pub fn new(name: &str) -> RuleRef {
+        return RuleRef {
+            name: name.to_string(),
+            index: None,
+        };
+    }
+
+    /// The name of the rule.
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// The position of the rule in the language.
+    pub fn index(&self) -> usize {
+        return self.index.expect("rule references are resolved when the language is built");
+    }
+}
+
+impl From<&str> for RuleRef {
+    fn from(name: &str) -> RuleRef {
+        return RuleRef::new(name);
+    }
+}
+
+/// A problem found while building a language.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LanguageError {
+    /// The language has no rules, so it has no file rule.
+    NoRules,
+
+    /// More than one rule was registered with the name.
+    DuplicateRule(String),
+
+    /// A rule refers to a name that no rule was registered with.
+    UnknownRule {
+        /// The rule with the bad reference.
+        rule: String,
+
+        /// The name it refers to.
+        name: String,
+    },
+
+    /// A rule with steps has none, so it has nowhere to start.
+    NoSteps(String),
+
+    /// An edge goes to a step that doesn't exist.
+    MissingStep {
+        /// The rule with the bad edge.
+        rule: String,
+
+        /// The step the edge leaves from.
+        step: usize,
+
+        /// The step the edge goes to.
+        target: usize,
+    },
+
+    /// A step can end the rule, but no path from the first step reaches it.
+    UnreachableStep {
+        /// The rule with the step.
+        rule: String,
+
+        /// The unreachable step.
+        step: usize,
+    },
+
+    /// No path from the first step reaches a step that can end the rule, so it always fails.
+    NeverAccepts(String),
+
+    /// A rule can go around a loop of edges that don't consume anything, so it never stops.
+    EmptyLoop {
+        /// The rule with the loop.
+        rule: String,
+
+        /// A step in the loop.
+        step: usize,
+    },
+
+    /// A lexer mode starts at a step that doesn't exist.
+    MissingStart {
+        /// The lexer with the mode.
+        rule: String,
+
+        /// The mode.
+        mode: usize,
+
+        /// The step it starts at.
+        step: usize,
+    },
+
+    /// A token enters a lexer mode that doesn't exist.
+    MissingMode {
+        /// The lexer with the token.
+        rule: String,
+
+        /// The mode entered.
+        mode: usize,
+    },
+
+    /// The first rule never creates a node of kind `Kind::FILE`.
+    NotFileRule(String),
+
+    /// A list's element and separator can both match nothing, so it can go round without
+    /// consuming anything.
+    EmptyList(String),
+
+    /// The rules can each be made of the next one alone, and the last of the first, so they
+    /// can match themselves forever without consuming anything.
+    Cycle(Vec<String>),
+}
+
+impl LanguageError {
+    /// The name of the rule the error is in, if it is in one.
+    pub fn rule(&self) -> Option<&str> {
+        return match self {
+            LanguageError::NoRules => None,
+            LanguageError::DuplicateRule(rule)
+            | LanguageError::UnknownRule { rule, .. }
+            | LanguageError::NoSteps(rule)
+            | LanguageError::MissingStep { rule, .. }
+            | LanguageError::UnreachableStep { rule, .. }
+            | LanguageError::NeverAccepts(rule)
+            | LanguageError::EmptyLoop { rule, .. }
+            | LanguageError::MissingStart { rule, .. }
+            | LanguageError::MissingMode { rule, .. }
+            | LanguageError::NotFileRule(rule)
+            | LanguageError::EmptyList(rule) => Some(rule),
+            LanguageError::Cycle(rules) => rules.first().map(|rule| rule.as_str()),
+        };
+    }
+}
+
+impl fmt::Display for LanguageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LanguageError::NoRules =>
+                write!(f, "the language has no rules"),
+            LanguageError::DuplicateRule(name) =>
+                write!(f, "the rule `{}` is defined more than once", name),
+            LanguageError::UnknownRule { rule, name } =>
+                write!(f, "the rule `{}` refers to `{}`, which is not defined", rule, name),
+            LanguageError::NoSteps(rule) =>
+                write!(f, "the rule `{}` has no steps", rule),
+            LanguageError::MissingStep { rule, step, target } =>
+                write!(f, "step {} of `{}` goes to step {}, which doesn't exist", step, rule, target),
+            LanguageError::UnreachableStep { rule, step } =>
+                write!(f, "step {} of `{}` can end the rule, but is never reached", step, rule),
+            LanguageError::NeverAccepts(rule) =>
+                write!(f, "the rule `{}` can never be finished", rule),
+            LanguageError::EmptyLoop { rule, step } =>
+                write!(f, "the rule `{}` can loop forever at step {} without consuming anything", rule, step),
+            LanguageError::MissingStart { rule, mode, step } =>
+                write!(f, "mode {} of `{}` starts at step {}, which doesn't exist", mode, rule, step),
+            LanguageError::MissingMode { rule, mode } =>
+                write!(f, "a token of `{}` enters mode {}, which doesn't exist", rule, mode),
+            LanguageError::NotFileRule(rule) =>
+                write!(f, "the file rule `{}` never creates a `File` node", rule),
+            LanguageError::EmptyList(rule) =>
+                write!(f, "the element and separator of the list `{}` can both be empty", rule),
+            LanguageError::Cycle(rules) if rules.len() == 1 =>
+                write!(f, "the rule `{}` can be made of itself without consuming anything", rules[0]),
+            LanguageError::Cycle(rules) =>
+                write!(f, "the rules `{}` can be made of each other without consuming anything", rules.join("`, `")),
+        }
+    }
+}
+
+impl std::error::Error for LanguageError {}
+
+/// A programming language is a list of named Rules, and the kinds of node they create.
+pub struct Language {
+    /// The rules of the language. The first one is the file rule.
+    pub rules: Vec<Box<dyn Rule>>,
+
+    /// The names of the kinds of node the rules create.
+    pub kinds: Kinds,
+
+    /// The position of each rule by name.
+    names: HashMap<String, usize>,
+
+    /// What is known about the rules before parsing anything.
+    pub analysis: Analysis,
+}
+
+impl Language {
+    /// Find the position of the rule with the given name.
+    pub fn rule(&self, name: &str) -> Option<usize> {
+        return self.names.get(name).copied();
+    }
+
+    /// Get the name of the rule at the given position.
+    pub fn rule_name(&self, index: usize) -> &str {
+        return self.names.iter()
+            .find(|(_, i)| **i == index)
+            .map(|(name, _)| name.as_str())
+            .expect("rule index out of range");
+    }
+}
+
+/// Collects the rules and kinds of a language.
+pub struct LanguageBuilder {
+    /// The named rules, in the order they were registered.
+    rules: Vec<(String, Box<dyn Rule>)>,
+
+    /// The kinds used by the rules.
+    kinds: Kinds,
+}
+
+impl LanguageBuilder {
+    /// Initializes an empty builder.
+    pub fn new() -> LanguageBuilder {
+        return LanguageBuilder {
+            rules: vec![],
+            kinds: Kinds::new(),
+        };
+    }
+
+    /// Get the kind with the given name, adding it if its new.
+    pub fn kind(&mut self, name: &str) -> Kind {
+        return self.kinds.intern(name);
+    }
+
+    /// Register a rule under a name. The first rule registered is the file rule.
+    pub fn rule(&mut self, name: &str, rule: Box<dyn Rule>) {
+        self.rules.push((name.to_string(), rule));
+    }
+
+    /// Resolve all the rule references, check the rules for mistakes, and build the language.
+    /// Every problem found is returned, rather than just the first.
+    pub fn build(self) -> Result<Language, Vec<LanguageError>> {
+        let mut errors = vec![];
+
+        if self.rules.is_empty() {
+            errors.push(LanguageError::NoRules);
+        }
+
+        let mut names = HashMap::new();
+        for (index, (name, _)) in self.rules.iter().enumerate() {
+            if names.insert(name.clone(), index).is_some() {
+                errors.push(LanguageError::DuplicateRule(name.clone()));
+            }
+        }
+
+        let mut rules = vec![];
+        let mut rule_names = vec![];
+        let mut uses = vec![];
+        for (name, mut rule) in self.rules {
+            let mut used = vec![];
+
+            for rule_ref in rule.refs() {
+                rule_ref.index = names.get(&rule_ref.name).copied();
+
+                if let Some(index) = rule_ref.index {
+                    used.push(index);
+                } else {
+                    errors.push(LanguageError::UnknownRule {
+                        rule: name.clone(),
+                        name: rule_ref.name.clone(),
+                    });
+                }
+            }
+
+            rules.push(rule);
+            rule_names.push(name);
+            uses.push(used);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // The rules can only be checked once every reference is resolved, and the analysis
+        // knows which rules can match nothing.
+        let analysis = Analysis::new(&rules, &rule_names, &uses);
+
+        for (rule, name) in rules.iter().zip(&rule_names) {
+            errors.extend(rule.validate(name, &rules, &analysis));
+        }
+
+        errors.extend(cycles(&rules, &rule_names, &analysis));
+
+        let kinds = rules[0].kinds();
+        if !kinds.is_empty() && !kinds.contains(&Kind::FILE) {
+            errors.push(LanguageError::NotFileRule(rule_names[0].clone()));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        return Ok(Language {
+            rules,
+            kinds: self.kinds,
+            names, analysis,
+        });
+    }
+}
+
+impl Default for LanguageBuilder {
+    fn default() -> LanguageBuilder {
+        return LanguageBuilder::new();
+    }
+}
+
+/// Find the rules that can be made of themselves alone. Each cycle is reported once, with its
+/// rules in the order they were registered.
+fn cycles(rules: &[Box<dyn Rule>], names: &[String], analysis: &Analysis) -> Vec<LanguageError> {
+    let sole: Vec<Vec<usize>> = rules.iter().map(|rule| rule.sole_rules(rules, analysis)).collect();
+
+    // The rules each rule can be made of alone, directly or through others.
+    let reaches: Vec<Vec<bool>> = (0..rules.len()).map(|start| {
+        let mut reached = vec![false; rules.len()];
+        let mut todo = sole[start].clone();
+
+        while let Some(index) = todo.pop() {
+            if !reached[index] {
+                reached[index] = true;
+                todo.extend(&sole[index]);
+            }
+        }
+
+        return reached;
+    }).collect();
+
+    let mut errors = vec![];
+    let mut reported = vec![false; rules.len()];
+
+    for start in 0..rules.len() {
+        if !reaches[start][start] || reported[start] {
+            continue;
+        }
+
+        let cycle: Vec<usize> = (0..rules.len()).filter(|index| reaches[start][*index] && reaches[*index][start]).collect();
+        for index in &cycle {
+            reported[*index] = true;
+        }
+
+        errors.push(LanguageError::Cycle(cycle.iter().map(|index| names[*index].clone()).collect()));
+    }
+
+    return errors;
+}
@@ -0,0 +1,187 @@
+use std::rc::Rc;
+use crate::document::{Kind, Node, Parser};
+use super::{Analysis, CharSet, First, Rule};
+
+/// Options for a layout rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LayoutOptions {
+    /// How many columns a tab moves to the next multiple of.
+    pub tab_width: usize,
+
+    /// The character that starts a line comment. Lines with only a comment on them are skipped
+    /// like blank lines.
+    pub comment: Option<char>,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> LayoutOptions {
+        return LayoutOptions {
+            tab_width: 8,
+            comment: None,
+        };
+    }
+}
+
+/// A rule for indentation sensitive languages, like Python.
+///
+/// At a line break it creates a newline node, which covers the line break, any blank or comment
+/// only lines after it, and the indentation of the next line. If the next line is indented
+/// more than the current block, an empty indent node follows. If it's indented less, one empty
+/// dedent node follows for each block it closes, and the last one has an error under it if the
+/// line doesn't line up with a block that is still open. Every block still open is closed at
+/// the end of the text.
+///
+/// The first line is measured too. If the text starts with blank lines or indentation, a
+/// newline node covers them, like it would after a line break.
+pub struct Layout {
+    /// The kind of node for a line break.
+    newline: Kind,
+
+    /// The kind of node for the start of a block.
+    indent: Kind,
+
+    /// The kind of node for the end of a block.
+    dedent: Kind,
+
+    /// How to measure the indentation.
+    options: LayoutOptions,
+}
+
+impl Layout {
+    /// Initializes a new Layout rule.
+    pub fn new(newline: Kind, indent: Kind, dedent: Kind, options: LayoutOptions) -> Box<dyn Rule> {
+        return Box::new(Layout { newline, indent, dedent, options });
+    }
+}
+
+impl Layout {
+    /// The characters a newline can start with. Away from the start of the text it starts with
+    /// a line break, but there it can start with indentation or a comment too.
+    fn newline_chars(&self) -> CharSet {
+        let mut ranges = vec![('\n', '\n'), ('\r', '\r'), ('\t', '\t'), (' ', ' ')];
+        ranges.extend(self.options.comment.map(|comment| (comment, comment)));
+
+        return CharSet::from_ranges(&ranges);
+    }
+
+    /// Eat a line break.
+    fn line_break(&self, parser: &mut Parser) -> bool {
+        let start = parser.save();
+
+        parser.next_if(|chr| *chr == '\r');
+        if parser.next_if(|chr| *chr == '\n') {
+            return true;
+        }
+
+        parser.restore(start);
+
+        return false;
+    }
+
+    /// Eat the indentation of the next line that has something on it, and return its width.
+    fn indentation(&self, parser: &mut Parser) -> usize {
+        loop {
+            let mut width = 0;
+
+            while let Some(chr) = parser.peek().filter(|chr| *chr == ' ' || *chr == '\t') {
+                parser.next_if(|_| true);
+
+                width = match chr {
+                    '\t' => (width / self.options.tab_width + 1) * self.options.tab_width,
+                    _ => width + 1,
+                };
+            }
+
+            if let Some(comment) = self.options.comment {
+                if parser.next_if(|chr| *chr == comment) {
+                    while parser.next_if(|chr| *chr != '\n' && *chr != '\r') {}
+                }
+            }
+
+            // There is nothing after the end of the text, so every block ends there.
+            if parser.at_end() {
+                return 0;
+            }
+
+            if !self.line_break(parser) {
+                return width;
+            }
+        }
+    }
+}
+
+impl Rule for Layout {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let offset = parser.offset;
+
+        // Finish matching the blocks to the indentation of the line we are at the start of.
+        if let Some(width) = parser.state().pending {
+            let state = parser.state_mut();
+
+            if width > state.indent() {
+                state.indents.push(width);
+                state.pending = None;
+
+                return Some((self.indent, vec![]));
+            }
+
+            state.indents.pop();
+
+            if state.indent() > width {
+                state.pending = Some(width);
+                return Some((self.dedent, vec![]));
+            }
+
+            state.pending = None;
+
+            // Going past the width means it was never the start of a block.
+            if state.indent() < width {
+                return Some((self.dedent, vec![parser.report("the indentation doesn't match any block")]));
+            }
+
+            return Some((self.dedent, vec![]));
+        }
+
+        if parser.at_end() {
+            if !parser.state().indents.is_empty() {
+                parser.state_mut().indents.pop();
+                return Some((self.dedent, vec![]));
+            }
+
+            return None;
+        }
+
+        if offset > 0 && !self.line_break(parser) {
+            return None;
+        }
+
+        let width = self.indentation(parser);
+
+        // There is nothing to cover at the start of a flat first line.
+        if parser.offset == 0 {
+            return None;
+        }
+
+        let state = parser.state_mut();
+        state.pending = if width == state.indent() { None } else { Some(width) };
+
+        return Some((self.newline, vec![]));
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        return vec![self.newline, self.indent, self.dedent];
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return First { nullable: true, chars: self.newline_chars() };
+    }
+
+    fn first_kind(&self, kind: Kind) -> Option<First> {
+        // Only a newline covers any text, and it never covers nothing.
+        if kind == self.newline {
+            return Some(First { nullable: false, chars: self.newline_chars() });
+        }
+
+        return Some(First { nullable: true, chars: CharSet::empty() });
+    }
+}
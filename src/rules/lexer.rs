@@ -1,40 +1,302 @@
-use std::ops::RangeInclusive;
 use std::rc::Rc;
 
 use crate::document::{Kind, Node, Parser};
+use crate::grammar::{regex_steps, RegexError};
 use crate::rules::Rule;
 
-use super::Step;
+use super::{step_kinds, validate_steps, Analysis, CharClass, CharSet, First, LanguageError, Step, Warning};
 
 pub struct Lexer {
-    pub steps: Vec<Step<RangeInclusive<char>>>
+    pub steps: Vec<Step<CharClass>>,
+
+    /// Should the lexer find the longest match, instead of taking the first edge that fits?
+    pub longest: bool,
+
+    /// The modes the lexer can be in, by the mode on top of the parser's mode stack.
+    pub modes: Vec<Mode>,
+}
+
+/// What happens to the mode stack after a token.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModeAction {
+    /// Enter the mode, on top of the current one.
+    Push(usize),
+
+    /// Leave the current mode.
+    Pop,
+}
+
+/// A mode of a lexer, with its own set of tokens.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Mode {
+    /// The step that the tokens of the mode start from.
+    pub start: usize,
+
+    /// What each kind of token lexed in the mode does to the mode stack.
+    pub actions: Vec<(Kind, ModeAction)>,
+}
+
+impl Mode {
+    /// A mode with tokens starting from the given step.
+    pub fn new(start: usize, actions: Vec<(Kind, ModeAction)>) -> Mode {
+        return Mode { start, actions };
+    }
 }
 
 impl Lexer {
-    pub fn new(steps: Vec<Step<RangeInclusive<char>>>) -> Box<dyn Rule> {
-        return Box::new(Lexer { steps });
+    pub fn new<T: Into<CharClass>>(steps: Vec<Step<T>>) -> Box<dyn Rule> {
+        return Box::new(Lexer { steps: classes(steps), longest: false, modes: vec![Mode::default()] });
+    }
+
+    /// A lexer that follows every edge that fits, and returns the longest token found. When
+    /// more than one step accepts the longest token, the earliest step wins.
+    pub fn longest<T: Into<CharClass>>(steps: Vec<Step<T>>) -> Box<dyn Rule> {
+        return Box::new(Lexer { steps: classes(steps), longest: true, modes: vec![Mode::default()] });
+    }
+
+    /// A longest match lexer with modes. The mode on top of the parser's mode stack picks the
+    /// step to start from, and tokens can enter and leave modes, like `${` entering the code
+    /// inside of a string. Mode 0 is used when no mode has been entered.
+    pub fn modes<T: Into<CharClass>>(steps: Vec<Step<T>>, modes: Vec<Mode>) -> Box<dyn Rule> {
+        return Box::new(Lexer { steps: classes(steps), longest: true, modes });
+    }
+
+    /// Build a lexer from one regular expression per kind of token, like `[a-z_]\w*`.
+    ///
+    /// The expressions are combined into one minimal dfa, which finds the longest match. It's
+    /// an error for two of them to match the same text, or for one to match the empty string.
+    pub fn from_regex(tokens: Vec<(&str, Kind)>) -> Result<Box<dyn Rule>, RegexError> {
+        return Ok(Lexer::longest(regex_steps(&tokens)?));
     }
 }
 
-impl Rule for Lexer {
-    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
-        let mut step = 0;
+impl Lexer {
+    /// The step each mode starts at.
+    fn starts(&self) -> Vec<usize> {
+        return self.modes.iter().map(|mode| mode.start).collect();
+    }
+
+    /// Follow every edge that fits at once, remembering the last place a step accepted.
+    fn parse_longest(&self, parser: &mut Parser, start: usize) -> Option<Kind> {
+        let mut active = vec![start];
+        let mut last = None;
 
-        while self.steps[step].rules().iter().any(|(range, i)| {
-            if parser.next_if(|chr| range.contains(chr)) {
-                step = *i;
+        loop {
+            let mut next = vec![];
 
-                true
-            } else {
-                false
+            let moved = parser.next_if(|chr| {
+                for &step in &active {
+                    for (class, i) in self.steps[step].rules() {
+                        if class.contains(*chr) && !next.contains(i) {
+                            next.push(*i);
+                        }
+                    }
+                }
+
+                !next.is_empty()
+            });
+
+            // At the end of the text, the edges that accept it can still be followed once.
+            if !moved && parser.at_end() {
+                for &step in &active {
+                    for (class, i) in self.steps[step].rules() {
+                        if class.is_end() && !next.contains(i) {
+                            next.push(*i);
+                        }
+                    }
+                }
             }
-        }) {}
 
-        if let Some(kind) = self.steps[step].kind() {
-            return Some((kind, vec![]));
+            if next.is_empty() {
+                break;
+            }
+
+            active = next;
+
+            // Ties go to the earliest step.
+            let accepted = active.iter()
+                .filter_map(|&step| self.steps[step].kind().map(|kind| (step, kind)))
+                .min_by_key(|(step, _)| *step);
+
+            if let Some((_, kind)) = accepted {
+                last = Some((parser.offset, kind));
+            }
+
+            if !moved {
+                break;
+            }
+        }
+
+        // Roll back to the end of the longest token.
+        let (offset, kind) = last?;
+        parser.offset = offset;
+
+        return Some(kind);
+    }
+}
+
+/// Turn the edges of the steps into character classes.
+pub fn classes<T: Into<CharClass>>(steps: Vec<Step<T>>) -> Vec<Step<CharClass>> {
+    return steps.into_iter().map(|Step(edges, kind)| Step(
+        edges.into_iter().map(|(class, i)| (class.into(), i)).collect(),
+        kind,
+    )).collect();
+}
+
+/// Follow the first edge of each step that fits, until none do. The end of the text is only
+/// followed once, as following it doesn't move forward.
+pub fn first_match(steps: &[Step<CharClass>], parser: &mut Parser, start: usize) -> Option<Kind> {
+    let mut step = start;
+    let mut ended = false;
+
+    while steps[step].rules().iter().any(|(class, i)| {
+        if parser.next_if(|chr| class.contains(*chr)) {
+            step = *i;
+
+            true
+        } else if !ended && class.is_end() && parser.at_end() {
+            step = *i;
+            ended = true;
+
+            true
         } else {
-            return None;
+            false
         }
+    }) {}
+
+    return steps[step].kind();
+}
+
+/// How steps made of character classes can start, from any of the starting steps. With a
+/// kind, only the paths that can end in a token of that kind count.
+pub fn steps_first(steps: &[Step<CharClass>], starts: &[usize], kind: Option<Kind>) -> First {
+    let mut first = First::default();
+
+    if steps.is_empty() {
+        return first;
     }
+
+    // Find the steps that can lead to the end of a token of the kind.
+    let mut leads: Vec<bool> = steps.iter()
+        .map(|step| step.kind().is_some() && kind.map_or(true, |kind| step.kind() == Some(kind)))
+        .collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for (step, Step(edges, _)) in steps.iter().enumerate() {
+            if !leads[step] && edges.iter().any(|(_, i)| *i < steps.len() && leads[*i]) {
+                leads[step] = true;
+                changed = true;
+            }
+        }
+    }
+
+    for &start in starts.iter().filter(|start| **start < steps.len()) {
+        first.nullable |= steps[start].kind().is_some() && leads[start];
+
+        for (class, i) in steps[start].rules() {
+            if *i < steps.len() && leads[*i] {
+                first.chars.union(&class.chars());
+                first.nullable |= class.is_end();
+            }
+        }
+    }
+
+    return first;
+}
+
+/// Find the edges of first match steps that never fire, or only fire for some of their
+/// characters, because the edges before them take those characters.
+pub fn steps_warnings(name: &str, steps: &[Step<CharClass>]) -> Vec<Warning> {
+    let mut warnings = vec![];
+
+    for (step, Step(edges, _)) in steps.iter().enumerate() {
+        let chars: Vec<CharSet> = edges.iter().map(|(class, _)| class.chars()).collect();
+
+        let mut taken = CharSet::empty();
+        let mut ended = false;
+
+        for (edge, (class, _)) in edges.iter().enumerate() {
+            let ends = class.is_end() && !ended;
+            ended |= class.is_end();
+
+            if taken.covers(&chars[edge]) && !ends {
+                warnings.push(Warning::ShadowedEdge { rule: name.to_string(), step, edge });
+            } else if let Some(first) = chars[..edge].iter().position(|other| other.intersects(&chars[edge])) {
+                warnings.push(Warning::Ambiguous { rule: name.to_string(), step, first, second: edge });
+            }
+
+            taken.union(&chars[edge]);
+        }
+    }
+
+    return warnings;
 }
 
+impl Rule for Lexer {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let mode = self.modes.get(parser.state().mode());
+        let start = mode.map_or(0, |mode| mode.start);
+
+        let kind = if self.longest {
+            self.parse_longest(parser, start)?
+        } else {
+            first_match(&self.steps, parser, start)?
+        };
+
+        // Some tokens enter or leave a mode.
+        let actions = mode.into_iter().flat_map(|mode| mode.actions.iter());
+        for (_, action) in actions.filter(|(token, _)| *token == kind) {
+            match action {
+                ModeAction::Push(mode) => parser.push_mode(*mode),
+                ModeAction::Pop => parser.pop_mode(),
+            }
+        }
+
+        return Some((kind, vec![]));
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        return step_kinds(&self.steps);
+    }
+
+    fn validate(&self, name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<LanguageError> {
+        let mut errors = validate_steps(name, &self.steps, &self.starts());
+
+        for (index, mode) in self.modes.iter().enumerate() {
+            // A lexer without steps is already an error.
+            if mode.start >= self.steps.len() && !self.steps.is_empty() {
+                errors.push(LanguageError::MissingStart { rule: name.to_string(), mode: index, step: mode.start });
+            }
+
+            for (_, action) in &mode.actions {
+                if let ModeAction::Push(target) = action {
+                    if *target >= self.modes.len() {
+                        errors.push(LanguageError::MissingMode { rule: name.to_string(), mode: *target });
+                    }
+                }
+            }
+        }
+
+        return errors;
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return steps_first(&self.steps, &self.starts(), None);
+    }
+
+    fn first_kind(&self, kind: Kind) -> Option<First> {
+        return Some(steps_first(&self.steps, &self.starts(), Some(kind)));
+    }
+
+    fn warnings(&self, name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<Warning> {
+        // Every edge that fits is followed when finding the longest match.
+        if self.longest {
+            return vec![];
+        }
+
+        return steps_warnings(name, &self.steps);
+    }
+}
@@ -0,0 +1,136 @@
+use std::rc::Rc;
+use crate::document::{Kind, Node, Parser};
+use super::{Analysis, First, LanguageError, Rule, RuleRef};
+
+/// Options for a separated list.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ListOptions {
+    /// Is a separator allowed after the last element?
+    pub trailing: bool,
+
+    /// The fewest elements the list can have. Zero allows the list to be empty.
+    pub min: usize,
+}
+
+impl Default for ListOptions {
+    fn default() -> ListOptions {
+        return ListOptions {
+            trailing: false,
+            min: 1,
+        };
+    }
+}
+
+/// A rule for a list of elements with separators between them, like `a, b, c`.
+pub struct List {
+    /// The rule and kind of each element.
+    element: (RuleRef, Kind),
+
+    /// The rule and kind of each separator.
+    separator: (RuleRef, Kind),
+
+    /// The kind of node the list creates.
+    kind: Kind,
+
+    /// How the list is allowed to look.
+    options: ListOptions,
+}
+
+impl List {
+    /// Initializes a new List. The elements and separators are the children of its node.
+    pub fn new(
+        element: (&str, Kind),
+        separator: (&str, Kind),
+        kind: Kind,
+        options: ListOptions,
+    ) -> Box<dyn Rule> {
+        return Box::new(List {
+            element: (RuleRef::new(element.0), element.1),
+            separator: (RuleRef::new(separator.0), separator.1),
+            kind, options,
+        });
+    }
+}
+
+impl Rule for List {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let mut subs = vec![];
+        let mut count = 0;
+
+        loop {
+            let start = parser.offset;
+
+            if let Some(element) = parser.parse_kind(self.element.0.index(), self.element.1) {
+                subs.push(element);
+                count += 1;
+            } else {
+                break;
+            }
+
+            if let Some(separator) = parser.parse_kind(self.separator.0.index(), self.separator.1) {
+                subs.push(separator);
+            } else {
+                break;
+            }
+
+            // Going round again without moving would never end.
+            if parser.offset == start {
+                break;
+            }
+        }
+
+        // Give back a separator that wasn't followed by an element, if it's not allowed.
+        if !subs.is_empty() && subs.len() % 2 == 0 && !self.options.trailing {
+            parser.rewind(&subs.pop().unwrap());
+        }
+
+        if count < self.options.min {
+            return None;
+        }
+
+        return Some((self.kind, subs));
+    }
+
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        return vec![&mut self.element.0, &mut self.separator.0];
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        return vec![self.kind];
+    }
+
+    fn validate(&self, name: &str, rules: &[Box<dyn Rule>], analysis: &Analysis) -> Vec<LanguageError> {
+        let element = analysis.first_kind(rules, self.element.0.index(), self.element.1);
+        let separator = analysis.first_kind(rules, self.separator.0.index(), self.separator.1);
+
+        if element.known_nullable() && separator.known_nullable() {
+            return vec![LanguageError::EmptyList(name.to_string())];
+        }
+
+        return vec![];
+    }
+
+    fn sole_rules(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<usize> {
+        // A single element with nothing after it.
+        if self.options.min <= 1 {
+            return vec![self.element.0.index()];
+        }
+
+        return vec![];
+    }
+
+    fn first(&self, rules: &[Box<dyn Rule>], analysis: &Analysis) -> First {
+        let (element, kind) = &self.element;
+        let mut first = analysis.first_kind(rules, element.index(), *kind);
+
+        // An empty element lets the list start with a separator.
+        if first.nullable {
+            let (separator, kind) = &self.separator;
+            first.chars.union(&analysis.first_kind(rules, separator.index(), *kind).chars);
+        }
+
+        first.nullable |= self.options.min == 0;
+
+        return first;
+    }
+}
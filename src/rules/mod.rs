@@ -4,11 +4,31 @@
 mod automata; 
 mod symbol;
 mod lexer;
+mod list;
+mod pratt;
+mod predicate;
+mod language;
+mod analysis;
+mod charclass;
+mod scanner;
+mod layout;
+mod tables;
+
+#[cfg(test)]
+mod tests;
 
 // Publish
 pub use automata::*;
 pub use symbol::*;
 pub use lexer::*;
+pub use list::*;
+pub use pratt::*;
+pub use predicate::*;
+pub use language::*;
+pub use analysis::*;
+pub use charclass::*;
+pub use scanner::*;
+pub use layout::*;
 
 use std::rc::Rc;
 use crate::document::*;
@@ -17,6 +37,49 @@ use crate::document::*;
 pub trait Rule {
     /// Parse the rule.
     fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)>;
+
+    /// The references to other rules, so that they can be resolved.
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        return vec![];
+    }
+
+    /// Is this a predicate, which only tests the text without consuming it or creating a node?
+    fn predicate(&self) -> bool {
+        return false;
+    }
+
+    /// The kinds of node the rule can create. Empty if the rule doesn't know.
+    fn kinds(&self) -> Vec<Kind> {
+        return vec![];
+    }
+
+    /// Check the rule for mistakes, once its references are resolved and the analysis knows
+    /// which rules can match nothing.
+    fn validate(&self, _name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<LanguageError> {
+        return vec![];
+    }
+
+    /// The rules whose node can be the whole of this rule's node, with everything else in it
+    /// matching nothing. A rule that can be made of itself this way is a cycle.
+    fn sole_rules(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<usize> {
+        return vec![];
+    }
+
+    /// How the rule can start, given what the analysis knows about the other rules so far.
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return First::unknown();
+    }
+
+    /// How the rule can start when it creates a node of the given kind, if the rule can tell
+    /// without the analysis.
+    fn first_kind(&self, _kind: Kind) -> Option<First> {
+        return None;
+    }
+
+    /// Likely mistakes in the rule, once the analysis is done.
+    fn warnings(&self, _name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<Warning> {
+        return vec![];
+    }
 }
 
 impl PartialEq for dyn Rule {
@@ -29,7 +92,3 @@ impl PartialEq for dyn Rule {
         return a == b;
     }
 }
-
-/// A programming language is just a list of Rules. The first one should be the file rule.
-pub type Language = Vec<Box<dyn Rule>>;
-
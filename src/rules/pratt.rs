@@ -0,0 +1,223 @@
+use std::rc::Rc;
+use crate::document::{Kind, Node, Parser};
+use super::{Analysis, First, Rule, RuleRef};
+
+/// Which way a chain of operators with the same precedence groups.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+}
+
+/// Where an operator goes relative to its operands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fixity {
+    /// Before its operand, like `-a`.
+    Prefix,
+
+    /// Between its operands, like `a + b`.
+    Infix(Associativity),
+
+    /// After its operand, like `a?`.
+    Postfix,
+}
+
+/// An operator in a Pratt rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Operator {
+    /// The kind of token the operator is.
+    pub token: Kind,
+
+    /// How tightly the operator binds. Higher binds tighter.
+    pub precedence: u32,
+
+    /// Where the operator goes.
+    pub fixity: Fixity,
+
+    /// The kind of node created for the operation.
+    pub kind: Kind,
+}
+
+impl Operator {
+    /// A prefix operator.
+    pub fn prefix(token: Kind, precedence: u32, kind: Kind) -> Operator {
+        return Operator { token, precedence, fixity: Fixity::Prefix, kind };
+    }
+
+    /// An infix operator.
+    pub fn infix(token: Kind, precedence: u32, associativity: Associativity, kind: Kind) -> Operator {
+        return Operator { token, precedence, fixity: Fixity::Infix(associativity), kind };
+    }
+
+    /// A postfix operator.
+    pub fn postfix(token: Kind, precedence: u32, kind: Kind) -> Operator {
+        return Operator { token, precedence, fixity: Fixity::Postfix, kind };
+    }
+}
+
+/// An operator precedence rule for expressions.
+///
+/// Each operation creates a node holding its operands and operator token, so `1 + 2 * 3`
+/// is a node with `1`, `+` and a node for `2 * 3` as children. A lone operand is returned
+/// as is.
+///
+/// The argument the rule is parsed with is the lowest precedence of operator it takes. The
+/// operand of each operator is parsed as the rule again, with that operator's precedence, so
+/// unedited operands are reused on reparse.
+pub struct Pratt {
+    /// The rules and kinds an operand can be, tried in order.
+    operands: Vec<(RuleRef, Kind)>,
+
+    /// The rule that reads the operator tokens.
+    operator: RuleRef,
+
+    /// The operator table.
+    operators: Vec<Operator>,
+}
+
+impl Pratt {
+    /// Initializes a new Pratt rule.
+    pub fn new(operands: Vec<(&str, Kind)>, operator: &str, operators: Vec<Operator>) -> Box<dyn Rule> {
+        return Box::new(Pratt {
+            operands: operands.into_iter().map(|(rule, kind)| (RuleRef::new(rule), kind)).collect(),
+            operator: RuleRef::new(operator),
+            operators,
+        });
+    }
+}
+
+impl Pratt {
+    /// Parse an operand.
+    fn operand<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<Rc<Node<'a>>> {
+        return self.operands.iter().find_map(|(rule, kind)| parser.parse_kind(rule.index(), *kind));
+    }
+
+    /// Parse an operator token with one of the given fixities.
+    fn operator<'a>(
+        &self,
+        parser: &mut Parser<'a, '_>,
+        fixity: impl Fn(Fixity) -> bool,
+    ) -> Option<(Rc<Node<'a>>, &Operator)> {
+        let start = parser.save();
+
+        // An operator that covers no text could be applied forever.
+        if let Some(token) = parser.parse(self.operator.index()).filter(|token| token.span.1 > token.span.0) {
+            let operator = self.operators.iter().find(|op| op.token == token.kind && fixity(op.fixity));
+
+            if let Some(operator) = operator {
+                return Some((token, operator));
+            }
+        }
+
+        parser.restore(start);
+
+        return None;
+    }
+
+    /// Parse an expression whose operators all have at least the given precedence.
+    fn expression<'a>(&self, parser: &mut Parser<'a, '_>, min: u32) -> Option<Rc<Node<'a>>> {
+        let start = parser.save();
+
+        let mut lhs = if let Some((token, op)) = self.operator(parser, |fixity| fixity == Fixity::Prefix) {
+            if let Some(rhs) = parser.parse_with(parser.rule(), op.precedence) {
+                parser.node(op.kind, vec![token, rhs])
+            } else {
+                parser.restore(start);
+                return None;
+            }
+        } else {
+            self.operand(parser)?
+        };
+
+        loop {
+            let checkpoint = parser.save();
+
+            let (token, op) = match self.operator(parser, |fixity| fixity != Fixity::Prefix) {
+                Some((token, op)) if op.precedence >= min => (token, op),
+                _ => {
+                    parser.restore(checkpoint);
+                    break;
+                },
+            };
+
+            lhs = match op.fixity {
+                Fixity::Infix(associativity) => {
+                    let min = match associativity {
+                        Associativity::Left => op.precedence + 1,
+                        Associativity::Right => op.precedence,
+                    };
+
+                    if let Some(rhs) = parser.parse_with(parser.rule(), min) {
+                        parser.node(op.kind, vec![lhs, token, rhs])
+                    } else {
+                        parser.restore(checkpoint);
+                        break;
+                    }
+                },
+                _ => parser.node(op.kind, vec![lhs, token]),
+            };
+        }
+
+        return Some(lhs);
+    }
+}
+
+impl Rule for Pratt {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let node = self.expression(parser, parser.arg())?;
+
+        return Some((node.kind, node.subs.clone()));
+    }
+
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        let mut refs: Vec<&mut RuleRef> = self.operands.iter_mut().map(|(rule, _)| rule).collect();
+        refs.push(&mut self.operator);
+
+        return refs;
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        let mut kinds = vec![];
+
+        // With no operators, the operand itself is the result.
+        let operands = self.operands.iter().map(|(_, kind)| *kind);
+        let operators = self.operators.iter().map(|operator| operator.kind);
+
+        for kind in operands.chain(operators) {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+
+        return kinds;
+    }
+
+    fn sole_rules(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<usize> {
+        // A lone operand.
+        return self.operands.iter().map(|(rule, _)| rule.index()).collect();
+    }
+
+    fn first(&self, rules: &[Box<dyn Rule>], analysis: &Analysis) -> First {
+        let mut first = First::default();
+
+        for (rule, kind) in &self.operands {
+            let operand = analysis.first_kind(rules, rule.index(), *kind);
+
+            first.nullable |= operand.nullable;
+            first.chars.union(&operand.chars);
+        }
+
+        // Prefix operators come before the operand, and the rest can follow an empty one.
+        for op in &self.operators {
+            if op.fixity == Fixity::Prefix || first.nullable {
+                let operator = analysis.first_kind(rules, self.operator.index(), op.token);
+                first.chars.union(&operator.chars);
+            }
+        }
+
+        return first;
+    }
+}
@@ -0,0 +1,75 @@
+use std::rc::Rc;
+use crate::document::{Kind, Node, Parser};
+use super::{Analysis, First, Rule, RuleRef};
+
+/// A predicate that matches if the rule matches at the current position, without
+/// consuming anything or creating a node.
+pub struct And {
+    /// The rule and kind to look for.
+    rule: (RuleRef, Kind),
+}
+
+impl And {
+    /// Initializes a new And predicate.
+    pub fn new(rule: (&str, Kind)) -> Box<dyn Rule> {
+        return Box::new(And { rule: (RuleRef::new(rule.0), rule.1) });
+    }
+}
+
+impl Rule for And {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let start = parser.save();
+        let found = parser.parse_kind(self.rule.0.index(), self.rule.1).is_some();
+        parser.restore(start);
+
+        return if found { Some((self.rule.1, vec![])) } else { None };
+    }
+
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        return vec![&mut self.rule.0];
+    }
+
+    fn predicate(&self) -> bool {
+        return true;
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return First { nullable: true, ..First::default() };
+    }
+}
+
+/// A predicate that matches if the rule doesn't match at the current position, without
+/// consuming anything or creating a node.
+pub struct Not {
+    /// The rule and kind that can't follow.
+    rule: (RuleRef, Kind),
+}
+
+impl Not {
+    /// Initializes a new Not predicate.
+    pub fn new(rule: (&str, Kind)) -> Box<dyn Rule> {
+        return Box::new(Not { rule: (RuleRef::new(rule.0), rule.1) });
+    }
+}
+
+impl Rule for Not {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let start = parser.save();
+        let found = parser.parse_kind(self.rule.0.index(), self.rule.1).is_some();
+        parser.restore(start);
+
+        return if found { None } else { Some((self.rule.1, vec![])) };
+    }
+
+    fn refs(&mut self) -> Vec<&mut RuleRef> {
+        return vec![&mut self.rule.0];
+    }
+
+    fn predicate(&self) -> bool {
+        return true;
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return First { nullable: true, ..First::default() };
+    }
+}
@@ -0,0 +1,179 @@
+use std::rc::Rc;
+use crate::document::{Kind, Node, Parser};
+use super::{Analysis, CharSet, First, Rule};
+
+/// A hand written tokenizer, for tokens that a Lexer can't describe, like nested comments.
+pub trait Scanner {
+    /// Read a token, returning its kind. Returning None means there is no token here.
+    fn scan(&self, scan: &mut Scan) -> Option<Kind>;
+
+    /// How the tokens can start, so the parser knows when not to bother.
+    fn first(&self) -> First {
+        return First::unknown();
+    }
+}
+
+/// What a Scanner can see of the parser.
+pub struct Scan<'p, 'a, 'b> {
+    /// The parser being scanned.
+    parser: &'p mut Parser<'a, 'b>,
+
+    /// Where the token ends, if it was marked.
+    end: Option<usize>,
+}
+
+impl Scan<'_, '_, '_> {
+    /// Look at the next character, without moving past it.
+    pub fn peek(&self) -> Option<char> {
+        return self.parser.peek();
+    }
+
+    /// Move past the next character, and return it.
+    pub fn advance(&mut self) -> Option<char> {
+        let chr = self.parser.peek()?;
+
+        return if self.parser.next_if(|_| true) { Some(chr) } else { None };
+    }
+
+    /// End the token here. Anything read after this is only looked at, not part of the token.
+    /// If this is never called, the token ends at the last character read.
+    pub fn mark_end(&mut self) {
+        self.end = Some(self.parser.offset);
+    }
+}
+
+/// A rule that uses a Scanner to read a token.
+pub struct External {
+    /// The scanner.
+    scanner: Box<dyn Scanner>,
+}
+
+impl External {
+    /// Initializes a new External rule.
+    pub fn new(scanner: impl Scanner + 'static) -> Box<dyn Rule> {
+        return Box::new(External { scanner: Box::new(scanner) });
+    }
+}
+
+impl Rule for External {
+    fn parse<'a>(&self, parser: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        let mut scan = Scan { parser, end: None };
+
+        let kind = self.scanner.scan(&mut scan)?;
+
+        if let Some(end) = scan.end {
+            scan.parser.offset = end;
+        }
+
+        return Some((kind, vec![]));
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return self.scanner.first();
+    }
+}
+
+/// A block comment that can have other block comments inside of it, like `/* a /* b */ */`.
+pub struct NestedComment {
+    /// The kind of token.
+    kind: Kind,
+}
+
+impl NestedComment {
+    /// Initializes a new NestedComment scanner.
+    pub fn new(kind: Kind) -> NestedComment {
+        return NestedComment { kind };
+    }
+}
+
+impl Scanner for NestedComment {
+    fn scan(&self, scan: &mut Scan) -> Option<Kind> {
+        if scan.advance()? != '/' || scan.advance()? != '*' {
+            return None;
+        }
+
+        let mut depth = 1;
+
+        // An unclosed comment isn't a comment.
+        loop {
+            match scan.advance()? {
+                '/' if scan.peek() == Some('*') => {
+                    scan.advance();
+                    depth += 1;
+                },
+                '*' if scan.peek() == Some('/') => {
+                    scan.advance();
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Some(self.kind);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn first(&self) -> First {
+        return First {
+            nullable: false,
+            chars: CharSet::from_ranges(&[('/', '/')]),
+        };
+    }
+}
+
+/// A Rust style raw string, like `r#"say "hi""#`. It ends at a quote followed by as many `#`
+/// as it started with.
+pub struct RawString {
+    /// The kind of token.
+    kind: Kind,
+}
+
+impl RawString {
+    /// Initializes a new RawString scanner.
+    pub fn new(kind: Kind) -> RawString {
+        return RawString { kind };
+    }
+}
+
+impl Scanner for RawString {
+    fn scan(&self, scan: &mut Scan) -> Option<Kind> {
+        if scan.advance()? != 'r' {
+            return None;
+        }
+
+        let mut hashes = 0;
+        while scan.peek() == Some('#') {
+            scan.advance();
+            hashes += 1;
+        }
+
+        if scan.advance()? != '"' {
+            return None;
+        }
+
+        loop {
+            if scan.advance()? != '"' {
+                continue;
+            }
+
+            // Count the hashes after the quote, stopping once there are enough.
+            let mut closing = 0;
+            while closing < hashes && scan.peek() == Some('#') {
+                scan.advance();
+                closing += 1;
+            }
+
+            if closing == hashes {
+                return Some(self.kind);
+            }
+        }
+    }
+
+    fn first(&self) -> First {
+        return First {
+            nullable: false,
+            chars: CharSet::from_ranges(&[('r', 'r')]),
+        };
+    }
+}
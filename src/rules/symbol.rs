@@ -1,39 +1,43 @@
-use std::{ops::RangeInclusive, rc::Rc};
+use std::rc::Rc;
 use crate::document::{Kind, Node, Parser};
-use super::{Rule, Step};
+use super::{classes, first_match, step_kinds, steps_first, steps_warnings, validate_steps};
+use super::{Analysis, CharClass, First, LanguageError, Rule, Step, Warning};
 
 /// A simpled definite finite automata rule for creating base lexing.
 pub struct Symbol {
     /// The Steps in the dfa.
-    steps: Vec<Step<RangeInclusive<char>>>,
+    steps: Vec<Step<CharClass>>,
 }
 
 impl Symbol {
     /// Initializes a new Symbol.
-    pub fn new(steps: Vec<Step<RangeInclusive<char>>>) -> Box<dyn Rule> {
-        return Box::new(Symbol { steps });
+    pub fn new<T: Into<CharClass>>(steps: Vec<Step<T>>) -> Box<dyn Rule> {
+        return Box::new(Symbol { steps: classes(steps) });
     }
 }
 
 impl Rule for Symbol {
     fn parse<'a>(&self, cursor: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
-        let mut step = 0;
-
-        while self.steps[step].rules().iter().any(|(range, i)| {
-            if cursor.next_if(|chr| range.contains(chr)) {
-                step = *i;
-
-                true
-            } else {
-                false
-            }
-        }) {}
-
-        if let Some(kind) = self.steps[step].kind() {
-            return Some((kind, vec![]));
-        } else {
-            return None;
-        }
+        return first_match(&self.steps, cursor, 0).map(|kind| (kind, vec![]));
+    }
+
+    fn kinds(&self) -> Vec<Kind> {
+        return step_kinds(&self.steps);
     }
-}
 
+    fn validate(&self, name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<LanguageError> {
+        return validate_steps(name, &self.steps, &[0]);
+    }
+
+    fn first(&self, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> First {
+        return steps_first(&self.steps, &[0], None);
+    }
+
+    fn first_kind(&self, kind: Kind) -> Option<First> {
+        return Some(steps_first(&self.steps, &[0], Some(kind)));
+    }
+
+    fn warnings(&self, name: &str, _rules: &[Box<dyn Rule>], _analysis: &Analysis) -> Vec<Warning> {
+        return steps_warnings(name, &self.steps);
+    }
+}
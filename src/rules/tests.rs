@@ -0,0 +1,279 @@
+use std::rc::Rc;
+use crate::document::{Document, Kind, Node, Parser};
+use super::*;
+
+/// Makes a rule from the kinds of name and `+` tokens.
+type Make = fn(Kind, Kind) -> Box<dyn Rule>;
+
+/// A language with a lexer of lowercase names and `+`, and the given rules. The first rule is
+/// the file rule.
+fn build(rules: Vec<(&str, Make)>) -> Result<Language, Vec<LanguageError>> {
+    let mut language = LanguageBuilder::new();
+
+    let name = language.kind("Name");
+    let plus = language.kind("Plus");
+
+    for (rule, make) in rules {
+        language.rule(rule, make(name, plus));
+    }
+
+    language.rule("Lexer", Lexer::from_regex(vec![("[a-z]+", name), (r"\+", plus)]).unwrap());
+
+    return language.build();
+}
+
+/// A file of any number of names.
+fn names(name: Kind, _: Kind) -> Box<dyn Rule> {
+    return Automata::new(vec![Step(vec![(("Lexer", name), 0)], Some(Kind::FILE))]);
+}
+
+/// An optional name.
+fn maybe(name: Kind, _: Kind) -> Box<dyn Rule> {
+    return Automata::new(vec![Step(vec![(("Lexer", name), 1)], Some(name)), Step(vec![], Some(name))]);
+}
+
+#[test]
+fn loops_through_rules_that_can_match_nothing() {
+    fn file(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("Maybe", name), 0)], Some(Kind::FILE))]);
+    }
+
+    let errors = build(vec![("File", file), ("Maybe", maybe)]).err().unwrap();
+    assert_eq!(errors, vec![LanguageError::EmptyLoop { rule: "File".to_string(), step: 0 }]);
+
+    assert!(build(vec![("File", names), ("Maybe", maybe)]).is_ok());
+}
+
+#[test]
+fn loops_through_predicates() {
+    fn file(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("NotPlus", name), 0)], Some(Kind::FILE))]);
+    }
+
+    fn not_plus(_: Kind, plus: Kind) -> Box<dyn Rule> {
+        return Not::new(("Lexer", plus));
+    }
+
+    let errors = build(vec![("File", file), ("NotPlus", not_plus)]).err().unwrap();
+    assert_eq!(errors, vec![LanguageError::EmptyLoop { rule: "File".to_string(), step: 0 }]);
+}
+
+#[test]
+fn rules_made_of_themselves() {
+    fn file(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("A", name), 1)], None), Step(vec![], Some(Kind::FILE))]);
+    }
+
+    // A = B; B = A | Name;
+    fn a(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("B", name), 1)], None), Step(vec![], Some(name))]);
+    }
+
+    fn b(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("A", name), 1), (("Lexer", name), 1)], None), Step(vec![], Some(name))]);
+    }
+
+    let errors = build(vec![("File", file), ("A", a), ("B", b)]).err().unwrap();
+    assert_eq!(errors, vec![LanguageError::Cycle(vec!["A".to_string(), "B".to_string()])]);
+
+    // A = A Plus Name | Name; is left recursive, but always consumes something around itself.
+    fn sum(name: Kind, plus: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![
+            Step(vec![(("A", name), 1), (("Lexer", name), 3)], None),
+            Step(vec![(("Lexer", plus), 2)], None),
+            Step(vec![(("Lexer", name), 3)], None),
+            Step(vec![], Some(name)),
+        ]);
+    }
+
+    assert!(build(vec![("File", file), ("A", sum)]).is_ok());
+
+    // Going through something that can match nothing is still a cycle.
+    fn padded(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![
+            Step(vec![(("Maybe", name), 1)], None),
+            Step(vec![(("A", name), 2), (("Lexer", name), 2)], None),
+            Step(vec![], Some(name)),
+        ]);
+    }
+
+    let errors = build(vec![("File", file), ("A", padded), ("Maybe", maybe)]).err().unwrap();
+    assert_eq!(errors, vec![LanguageError::Cycle(vec!["A".to_string()])]);
+}
+
+#[test]
+fn lists_with_an_empty_element_and_separator() {
+    fn file(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("List", name), 1)], None), Step(vec![], Some(Kind::FILE))]);
+    }
+
+    fn list(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return List::new(("Maybe", name), ("Maybe", name), name, ListOptions::default());
+    }
+
+    fn separated(name: Kind, plus: Kind) -> Box<dyn Rule> {
+        return List::new(("Maybe", name), ("Lexer", plus), name, ListOptions::default());
+    }
+
+    let errors = build(vec![("File", file), ("List", list), ("Maybe", maybe)]).err().unwrap();
+    assert!(errors.contains(&LanguageError::EmptyList("List".to_string())));
+
+    assert!(build(vec![("File", file), ("List", separated), ("Maybe", maybe)]).is_ok());
+}
+
+/// A name, made into a node of its own.
+fn word(name: Kind, _: Kind) -> Box<dyn Rule> {
+    return Automata::new(vec![Step(vec![(("Lexer", name), 1)], None), Step(vec![], Some(name))]);
+}
+
+#[test]
+fn first_sets_and_nullability() {
+    fn file(name: Kind, _: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![
+            Step(vec![(("Maybe", name), 1)], None),
+            Step(vec![(("Word", name), 1)], Some(Kind::FILE)),
+        ]);
+    }
+
+    let language = build(vec![("File", file), ("Word", word), ("Maybe", maybe)]).ok().unwrap();
+    let first = |rule: &str| language.analysis.first[language.rule(rule).unwrap()].clone();
+
+    assert_eq!(first("Word"), First { nullable: false, chars: CharSet::from_ranges(&[('a', 'z')]) });
+    assert_eq!(first("Maybe"), First { nullable: true, chars: CharSet::from_ranges(&[('a', 'z')]) });
+    assert_eq!(first("Lexer").chars, CharSet::from_ranges(&[('+', '+'), ('a', 'z')]));
+}
+
+/// A rule that can only start with `+`, and can't be parsed.
+struct PlusOnly;
+
+impl Rule for PlusOnly {
+    fn parse<'a>(&self, _: &mut Parser<'a, '_>) -> Option<(Kind, Vec<Rc<Node<'a>>>)> {
+        panic!("a rule was parsed where it can't start");
+    }
+
+    fn first(&self, _: &[Box<dyn Rule>], _: &Analysis) -> First {
+        return First { nullable: false, chars: CharSet::from_ranges(&[('+', '+')]) };
+    }
+}
+
+#[test]
+fn rules_are_only_tried_where_they_can_start() {
+    fn file(name: Kind, plus: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![(("PlusOnly", plus), 0), (("Lexer", name), 0)], Some(Kind::FILE))]);
+    }
+
+    fn plus_only(_: Kind, _: Kind) -> Box<dyn Rule> {
+        return Box::new(PlusOnly);
+    }
+
+    let language = build(vec![("File", file), ("PlusOnly", plus_only)]).ok().unwrap();
+
+    let mut document = Document::new(&language);
+    document.edit((0, 0), "ab");
+    assert_eq!(document.root.subs.len(), 1);
+}
+
+#[test]
+fn warnings() {
+    fn file(name: Kind, plus: Kind) -> Box<dyn Rule> {
+        return Automata::new(vec![Step(vec![
+            (("Lexer", name), 0),
+            (("Lexer", name), 0),
+            (("Word", name), 0),
+            (("Lexer", plus), 0),
+        ], Some(Kind::FILE))]);
+    }
+
+    let language = build(vec![("File", file), ("Word", word), ("Unused", word)]).ok().unwrap();
+
+    assert_eq!(language.analysis.warnings, vec![
+        Warning::UnreachableRule("Unused".to_string()),
+        Warning::ShadowedEdge { rule: "File".to_string(), step: 0, edge: 1 },
+        Warning::Ambiguous { rule: "File".to_string(), step: 0, first: 0, second: 2 },
+    ]);
+
+    // An ordered automata tries the later edges too, so they aren't mistakes.
+    fn ordered(name: Kind, plus: Kind) -> Box<dyn Rule> {
+        return Automata::ordered(vec![Step(vec![
+            (("Word", name), 0),
+            (("Lexer", name), 0),
+            (("Lexer", plus), 0),
+        ], Some(Kind::FILE))]);
+    }
+
+    let language = build(vec![("File", ordered), ("Word", word)]).ok().unwrap();
+    assert_eq!(language.analysis.warnings, vec![]);
+}
+
+/// Statements like `a=1;`, which recover from errors at the `;` that ends them.
+fn statements() -> Language {
+    let mut language = LanguageBuilder::new();
+
+    let name = language.kind("Name");
+    let number = language.kind("Number");
+    let punctuation = language.kind("Punctuation");
+    let statement = language.kind("Statement");
+
+    language.rule("File", Automata::new(vec![Step(vec![(("Statement", statement), 0)], Some(Kind::FILE))]));
+
+    language.rule("Statement", Automata::recovering(vec![
+        Step(vec![(Edge::new("Lexer", name), 1)], None),
+        Step(vec![(Edge::text("Lexer", punctuation, "="), 2)], None),
+        Step(vec![(Edge::new("Lexer", number), 3)], None),
+        Step(vec![(Edge::text("Lexer", punctuation, ";"), 4)], None),
+        Step(vec![], Some(statement)),
+    ], vec![Edge::text("Lexer", punctuation, ";")]));
+
+    language.rule("Lexer", Lexer::from_regex(vec![("[a-z]+", name), ("[0-9]+", number), ("[=;+]", punctuation)]).unwrap());
+
+    return language.build().ok().unwrap();
+}
+
+/// Parse the text, and write out the kinds and texts of its tree, like `Statement(Name(a))`.
+fn recovered(language: &Language, text: &str) -> String {
+    fn tree(document: &Document, node: &Rc<Node>) -> String {
+        let name = document.lang.kinds.name(node.kind);
+
+        if node.subs.is_empty() {
+            return format!("{}({})", name, document.node_text(node));
+        }
+
+        let subs: Vec<String> = node.subs.iter().map(|sub| tree(document, sub)).collect();
+        return format!("{}({})", name, subs.join(" "));
+    }
+
+    let mut document = Document::new(language);
+    document.edit((0, 0), text);
+
+    return tree(&document, &document.root);
+}
+
+#[test]
+fn recovering_fills_in_a_missing_node() {
+    let language = statements();
+
+    assert_eq!(
+        recovered(&language, "a1;b=2;"),
+        "File(Statement(Name(a) Punctuation() Number(1) Punctuation(;)) \
+            Statement(Name(b) Punctuation(=) Number(2) Punctuation(;)))",
+    );
+}
+
+#[test]
+fn recovering_skips_to_a_sync_node() {
+    let language = statements();
+
+    // The `+` is the same kind of token as a `;`, but not the text the sync edge takes.
+    assert_eq!(
+        recovered(&language, "a=+b+;c=2;"),
+        "File(Statement(Name(a) Punctuation(=) Error(+b+) Number() Punctuation(;)) \
+            Statement(Name(c) Punctuation(=) Number(2) Punctuation(;)))",
+    );
+
+    // At the end of the text, or at a sync node, the rest is filled in.
+    assert_eq!(
+        recovered(&language, "a;b=2"),
+        "File(Statement(Name(a) Punctuation() Number() Punctuation(;)) \
+            Statement(Name(b) Punctuation(=) Number(2) Punctuation()))",
+    );
+}
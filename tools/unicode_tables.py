@@ -0,0 +1,56 @@
+#!/usr/bin/env python3
+"""Generate src/rules/tables.rs, the Unicode tables used by `CharClass`.
+
+The tables come from the Unicode database bundled with Python, so regenerating them
+needs no network. Run from the root of the repository:
+
+    python3 tools/unicode_tables.py > src/rules/tables.rs
+"""
+
+import sys
+import unicodedata
+
+# PropList.txt White_Space, which hardly ever changes.
+WHITE_SPACE = [
+    (0x09, 0x0D), (0x20, 0x20), (0x85, 0x85), (0xA0, 0xA0), (0x1680, 0x1680),
+    (0x2000, 0x200A), (0x2028, 0x2029), (0x202F, 0x202F), (0x205F, 0x205F),
+    (0x3000, 0x3000),
+]
+
+
+def ranges(test):
+    """Collapse every code point passing the test into inclusive ranges."""
+    result = []
+
+    for point in range(sys.maxunicode + 1):
+        if 0xD800 <= point <= 0xDFFF or not test(chr(point)):
+            continue
+
+        if result and result[-1][1] == point - 1:
+            result[-1][1] = point
+        else:
+            result.append([point, point])
+
+    return result
+
+
+def table(name, doc, ranges):
+    print()
+    print(f"/// {doc}")
+    print(f"pub const {name}: &[(char, char)] = &[")
+    for start, end in ranges:
+        print(f"    ('\\u{{{start:x}}}', '\\u{{{end:x}}}'),")
+    print("];")
+
+
+print(f"//! Unicode {unicodedata.unidata_version} tables for `CharClass`.")
+print("//! Generated by `tools/unicode_tables.py`, don't edit by hand.")
+table("XID_START", "Characters that can start an identifier.",
+      ranges(lambda c: c != "_" and c.isidentifier()))
+table("XID_CONTINUE", "Characters that can continue an identifier.",
+      ranges(lambda c: ("a" + c).isidentifier()))
+table("WHITE_SPACE", "Whitespace characters.", WHITE_SPACE)
+table("LETTER", "Letters, in the general categories starting with L.",
+      ranges(lambda c: unicodedata.category(c).startswith("L")))
+table("DIGIT", "Decimal digits, in the general category Nd.",
+      ranges(lambda c: unicodedata.category(c) == "Nd"))